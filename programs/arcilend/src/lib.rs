@@ -1,5 +1,21 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program::invoke;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self as sysvar_instructions, get_instruction_relative, load_current_index_checked,
+    load_instruction_at_checked,
+};
 use anchor_lang::system_program;
+use anchor_lang::Discriminator;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{transfer_hook::TransferHook, BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+};
+use anchor_spl::stake::{self, Stake, StakeAccount};
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
+use solana_stake_interface::state::StakeAuthorize;
 
 declare_id!("CfuTSUUVQnPrMjSLwSoERGaDrAojWBfZ4UhCWAUNxuff");
 
@@ -13,7 +29,7 @@ pub mod arcilend {
         liquidation_threshold: u16
     ) -> Result<()> {
         require!(
-            collateral_ratio >= MIN_COLLATERAL_RATIO && collateral_ratio <= MAX_COLLATERAL_RATIO,
+            (MIN_COLLATERAL_RATIO..=MAX_COLLATERAL_RATIO).contains(&collateral_ratio),
             ArciLendError::InvalidCollateralRatio);
         require!(
             interest_rate <= BASIS_POINTS,
@@ -32,11 +48,40 @@ pub mod arcilend {
         lending_pool.interest_rate = interest_rate;
         lending_pool.collateral_ratio = collateral_ratio;
         lending_pool.liquidation_threshold = liquidation_threshold;
-        lending_pool.arcium_mcp_pubkey = ctx.accounts.arcium_mpc_pubkey.key();
+        lending_pool.mpc_nodes = [Pubkey::default(); MAX_MPC_NODES];
+        lending_pool.mpc_nodes[0] = ctx.accounts.arcium_mpc_pubkey.key();
+        lending_pool.mpc_node_count = 1;
+        lending_pool.mpc_threshold = 1;
+        lending_pool.mpc_key_rotation = MpcKeyRotation::default();
+        lending_pool.mxe_program = Pubkey::default();
+        lending_pool.mxe_signer = Pubkey::default();
+        lending_pool.mpc_operator = ctx.accounts.authority.key();
+        lending_pool.credit_score_fee = 0;
+        lending_pool.credit_score_cooldown = 0;
+        lending_pool.credit_score_fees_escrowed = 0;
+        lending_pool.credit_score_fees_claimable = 0;
+        lending_pool.risk_tiers = [RiskTier::default(); MAX_RISK_TIERS];
+        lending_pool.risk_tiers[0] = RiskTier { min_ltv: MIN_LTV, premium_bps: 200, max_loan: u64::MAX };
+        lending_pool.risk_tiers[1] = RiskTier { min_ltv: 7001, premium_bps: 0, max_loan: u64::MAX };
+        lending_pool.risk_tier_count = 2;
+        lending_pool.credit_line_min_ltv = MAX_LTV;
+        lending_pool.credit_line_max = 0;
+        lending_pool.credit_line_exposure_limit = 0;
+        lending_pool.credit_line_exposure = 0;
+        lending_pool.credit_line_reserve_fee_bps = 0;
+        lending_pool.credit_line_loss_reserve = 0;
+        lending_pool.bad_debt = 0;
         lending_pool.oracle_feed = ctx.accounts.oracle_feed.key();
         lending_pool.bump = ctx.bumps.lending_pool;
         lending_pool.utilization_rate = 0;
         lending_pool.total_fees = 0;
+        lending_pool.flash_fee_bps = DEFAULT_FLASH_FEE_BPS;
+        lending_pool.flash_loan_amount = 0;
+        lending_pool.supply_cap = u64::MAX;
+        lending_pool.borrow_cap = u64::MAX;
+        lending_pool.max_user_borrow = u64::MAX;
+        lending_pool.loan_term = LOAN_TERM;
+        lending_pool.total_stake_collateral = 0;
 
         msg!("Lending pool initialized!");
         msg!("Interest Rate {}bps", interest_rate);
@@ -48,6 +93,15 @@ pub mod arcilend {
         let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
         let user_account = &mut ctx.accounts.user_account;
         let lending_pool = &mut ctx.accounts.lending_pool;
+
+        // Carry over credit history earned in other pools. Only a fresh
+        // account imports, and only from a record kept by a trusted pool.
+        let imported = match ctx.accounts.imported_reputation.as_ref() {
+            Some(record) if user_account.owner == Pubkey::default() => {
+                Some(lending_pool.imported_reputation(record, &ctx.accounts.user.key())?)
+            }
+            _ => None,
+        };
     
         // Initialize user account if first time
         if user_account.owner == Pubkey::default() {
@@ -57,12 +111,37 @@ pub mod arcilend {
             user_account.last_update = Clock::get()?.unix_timestamp;
             user_account.loan_count = 0;
             user_account.encrypted_credit_score = [0u8; 32];
-            user_account.risk_adjusted_ltv = 5000;
+            user_account.risk_adjusted_ltv = DEFAULT_LTV;
+            user_account.credit_score_expires_at = 0;
+            user_account.total_repaid_volume = 0;
+            user_account.closed_loans = 0;
+            user_account.total_loan_duration = 0;
+            user_account.late_payments = 0;
+            user_account.max_utilization_bps = 0;
+            user_account.first_deposit_at = Clock::get()?.unix_timestamp;
+            user_account.liquidations = 0;
             user_account.successful_repayments = 0;
             user_account.defaults = 0;
             user_account.bump = ctx.bumps.user_account;
+
+            if let Some(history) = &imported {
+                user_account.import_reputation(history);
+            }
+        }
+
+        let reputation = &mut ctx.accounts.reputation;
+        if reputation.owner == Pubkey::default() {
+            reputation.owner = ctx.accounts.user.key();
+            reputation.bump = ctx.bumps.reputation;
+
+            // Keep the imported history so the next pool can carry it over too
+            if let Some(history) = &imported {
+                reputation.import(history);
+            }
         }
     
+        lending_pool.check_supply_cap(amount)?;
+
         let cpi_context = CpiContext::new(
             ctx.accounts.system_program.to_account_info(),
             system_program::Transfer {
@@ -82,41 +161,327 @@ pub mod arcilend {
         Ok(())
     }
 
-    pub fn request_credit_score(ctx: Context<RequestCreditScore>) -> Result<()>{
+    pub fn configure_mxe(ctx: Context<ConfigureMxe>) -> Result<()> {
+        let mxe_program = ctx.accounts.mxe_program.key();
+        let (mxe_signer, _) = Pubkey::find_program_address(&[MXE_SIGNER_SEED], &mxe_program);
+
+        ctx.accounts.lending_pool.mxe_program = mxe_program;
+        ctx.accounts.lending_pool.mxe_signer = mxe_signer;
+
+        msg!("MXE program: {}", mxe_program);
+        msg!("MXE signer: {}", mxe_signer);
+
+        Ok(())
+    }
+
+    pub fn set_mpc_nodes(ctx: Context<SetMpcNodes>, nodes: Vec<Pubkey>, threshold: u8) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+
+        require!(
+            !nodes.is_empty() && nodes.len() <= MAX_MPC_NODES,
+            ArciLendError::InvalidMpcNodeSet
+        );
+        require!(
+            threshold > 0 && threshold as usize <= nodes.len(),
+            ArciLendError::InvalidMpcThreshold
+        );
+        for (i, node) in nodes.iter().enumerate() {
+            require!(
+                *node != Pubkey::default() && !nodes[..i].contains(node),
+                ArciLendError::InvalidMpcNodeSet
+            );
+        }
+
+        lending_pool.mpc_nodes = [Pubkey::default(); MAX_MPC_NODES];
+        lending_pool.mpc_nodes[..nodes.len()].copy_from_slice(&nodes);
+        lending_pool.mpc_node_count = nodes.len() as u8;
+        lending_pool.mpc_threshold = threshold;
+        lending_pool.mpc_key_rotation = MpcKeyRotation::default();
+
+        msg!("MPC nodes updated: {}-of-{}", threshold, nodes.len());
+
+        Ok(())
+    }
+
+    pub fn set_credit_score_config(
+        ctx: Context<SetCreditScoreConfig>,
+        fee: u64,
+        cooldown: i64,
+        mpc_operator: Pubkey
+    ) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+
+        require!(
+            (0..=MAX_CREDIT_SCORE_COOLDOWN).contains(&cooldown),
+            ArciLendError::InvalidCreditScoreConfig
+        );
+
+        lending_pool.credit_score_fee = fee;
+        lending_pool.credit_score_cooldown = cooldown;
+        lending_pool.mpc_operator = mpc_operator;
+
+        msg!("Credit score fee: {} lamports, cooldown: {}s", fee, cooldown);
+
+        Ok(())
+    }
+
+    pub fn schedule_mpc_key_rotation(
+        ctx: Context<ScheduleMpcKeyRotation>,
+        old_key: Pubkey,
+        new_key: Pubkey,
+        activates_at: i64,
+        overlap: i64
+    ) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let clock = Clock::get()?;
+        let rotation = &lending_pool.mpc_key_rotation;
+
+        require!(
+            rotation.old_key == Pubkey::default()
+                || (rotation.activated && clock.unix_timestamp >= rotation.overlap_ends_at),
+            ArciLendError::MpcKeyRotationPending
+        );
+        require!(
+            lending_pool.mpc_nodes[..lending_pool.mpc_node_count as usize].contains(&old_key),
+            ArciLendError::InvalidMpcKeyRotation
+        );
+        require!(
+            new_key != Pubkey::default()
+                && !lending_pool.mpc_nodes[..lending_pool.mpc_node_count as usize].contains(&new_key),
+            ArciLendError::InvalidMpcKeyRotation
+        );
+        require!(
+            activates_at > clock.unix_timestamp && (0..=MAX_MPC_KEY_OVERLAP).contains(&overlap),
+            ArciLendError::InvalidMpcKeyRotation
+        );
+
+        lending_pool.mpc_key_rotation = MpcKeyRotation {
+            old_key,
+            new_key,
+            activates_at,
+            overlap_ends_at: activates_at + overlap,
+            activated: false,
+        };
+
+        emit!(
+            MpcKeyRotationScheduled {
+                old_key,
+                new_key,
+                activates_at,
+                overlap_ends_at: activates_at + overlap,
+            }
+        );
+
+        msg!("MPC key rotation scheduled for {}", activates_at);
+
+        Ok(())
+    }
+
+    /// Permissionless once the activation time has passed; swaps the new key
+    /// into the node set while the old key stays valid until the overlap ends
+    pub fn activate_mpc_key_rotation(ctx: Context<ActivateMpcKeyRotation>) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let clock = Clock::get()?;
+        let rotation = lending_pool.mpc_key_rotation;
+
+        require!(
+            rotation.old_key != Pubkey::default() && !rotation.activated,
+            ArciLendError::InvalidMpcKeyRotation
+        );
+        require!(
+            clock.unix_timestamp >= rotation.activates_at,
+            ArciLendError::MpcKeyRotationPending
+        );
+
+        let count = lending_pool.mpc_node_count as usize;
+        if let Some(slot) = lending_pool.mpc_nodes[..count].iter_mut().find(|node| **node == rotation.old_key) {
+            *slot = rotation.new_key;
+        }
+        lending_pool.mpc_key_rotation.activated = true;
+
+        emit!(
+            MpcKeyRotationActivated {
+                old_key: rotation.old_key,
+                new_key: rotation.new_key,
+                overlap_ends_at: rotation.overlap_ends_at,
+                timestamp: clock.unix_timestamp,
+            }
+        );
+
+        msg!("MPC key rotation activated");
+
+        Ok(())
+    }
+
+    pub fn request_credit_score(
+        ctx: Context<RequestCreditScore>,
+        computation_offset: u64,
+        encryption_pubkey: [u8; 32],
+        nonce: u128
+    ) -> Result<()>{
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
         let user_account = &ctx.accounts.user_account;
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let computation = &mut ctx.accounts.credit_computation;
+        let clock = Clock::get()?;
+
+        require!(lending_pool.mxe_program != Pubkey::default(), ArciLendError::MxeNotConfigured);
+        require!(
+            !computation.pending || clock.unix_timestamp > computation.deadline,
+            ArciLendError::ComputationPending
+        );
+        require!(
+            clock.unix_timestamp >= computation.queued_at + lending_pool.credit_score_cooldown,
+            ArciLendError::CreditScoreCooldown
+        );
+
+        // The fee of an expired request was never earned by the operator
+        if computation.pending {
+            lending_pool.credit_score_fees_escrowed -= computation.fee_paid;
+            lending_pool.total_fees += computation.fee_paid;
+        }
+
+        let fee = lending_pool.credit_score_fee;
+        if fee > 0 {
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: lending_pool_info,
+                },
+            );
+            system_program::transfer(cpi_context, fee)?;
+        }
+        lending_pool.credit_score_fees_escrowed += fee;
+
+        // A fresh nonce per request so stale or replayed results are rejected
+        let request_nonce = computation.request_nonce + 1;
+        let deadline = clock.unix_timestamp + CREDIT_SCORE_REQUEST_TTL;
+
+        let history = user_account.credit_history(clock.unix_timestamp);
+        let inputs = CreditScoreInputs {
+            user: user_account.owner,
+            request_nonce,
+            history: history.clone(),
+            encryption_pubkey,
+            nonce,
+        };
+
+        let mut data = hash(b"global:queue_computation").to_bytes()[..8].to_vec();
+        data.extend_from_slice(&computation_offset.to_le_bytes());
+        data.extend_from_slice(crate::ID.as_ref());
+        inputs.serialize(&mut data)?;
+
+        let queue_ix = Instruction {
+            program_id: lending_pool.mxe_program,
+            accounts: vec![
+                AccountMeta::new(ctx.accounts.user.key(), true),
+                AccountMeta::new(ctx.accounts.mxe_computation.key(), false),
+                AccountMeta::new_readonly(system_program::ID, false),
+            ],
+            data,
+        };
+        invoke(
+            &queue_ix,
+            &[
+                ctx.accounts.user.to_account_info(),
+                ctx.accounts.mxe_computation.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                ctx.accounts.mxe_program.to_account_info(),
+            ],
+        )?;
+
+        computation.user = user_account.owner;
+        computation.computation_offset = computation_offset;
+        computation.request_nonce = request_nonce;
+        computation.pending = true;
+        computation.queued_at = clock.unix_timestamp;
+        computation.deadline = deadline;
+        computation.snapshot = history.clone();
+        computation.fee_paid = fee;
+        computation.bump = ctx.bumps.credit_computation;
 
         emit!(
             CreditScoreRequested{
                 user: user_account.owner.key(),
-                collateral_deposited: user_account.collateral_deposited,
-                amount_borrowed: user_account.amount_borrowed,
-                successful_repayments: user_account.successful_repayments,
-                defaults: user_account.defaults,
-                timestamp: Clock::get()?.unix_timestamp,
+                computation_offset,
+                request_nonce,
+                deadline,
+                collateral_deposited: history.collateral_deposited,
+                amount_borrowed: history.amount_borrowed,
+                successful_repayments: history.successful_repayments,
+                defaults: history.defaults,
+                total_repaid_volume: history.total_repaid_volume,
+                average_loan_duration: history.average_loan_duration,
+                late_payments: history.late_payments,
+                max_utilization_bps: history.max_utilization_bps,
+                account_age: history.account_age,
+                liquidations: history.liquidations,
+                timestamp: clock.unix_timestamp,
             }
         );
         
-        msg!("🔐 Credit score calculation requested");
+        msg!("🔐 Credit score calculation queued");
         msg!("User: {}", user_account.owner);
 
         Ok(())
     }
 
-    pub fn update_credit_score(ctx: Context<UpdateCreditScore>, encrypted_score: [u8;32], risk_adjusted_ltv: u16) -> Result<()>{
+    /// MXE callback carrying the output of a queued credit score computation.
+    /// Must be signed by the configured MXE and accompanied by Ed25519 precompile
+    /// instructions in which at least `mpc_threshold` registered MPC nodes sign
+    /// the canonical score update message.
+    pub fn update_credit_score(
+        ctx: Context<UpdateCreditScore>,
+        request_nonce: u64,
+        encrypted_score: [u8;32],
+        risk_adjusted_ltv: u16,
+        expiry: i64
+    ) -> Result<()>{
         let user_account = &mut ctx.accounts.user_account;
         let lending_pool = &mut ctx.accounts.lending_pool;
-        
+        let computation = &mut ctx.accounts.credit_computation;
+        let clock = Clock::get()?;
+
+        require!(lending_pool.mxe_program != Pubkey::default(), ArciLendError::MxeNotConfigured);
         require!(
-            ctx.accounts.mpc_authority.key() == lending_pool.arcium_mcp_pubkey,
+            ctx.accounts.mpc_authority.key() == lending_pool.mxe_signer,
             ArciLendError::UnauthorizedMPCUpdate
         );
+        require!(clock.unix_timestamp <= expiry, ArciLendError::AttestationExpired);
+
+        let message = score_update_message(
+            &user_account.owner,
+            &encrypted_score,
+            risk_adjusted_ltv,
+            request_nonce,
+            expiry,
+        );
+        let attestations = lending_pool.count_mpc_attestations(&ctx.accounts.instructions, &message, clock.unix_timestamp)?;
+
+        require!(
+            attestations >= lending_pool.mpc_threshold as usize,
+            ArciLendError::InsufficientAttestations
+        );
         require!(
-            risk_adjusted_ltv >= MIN_LTV && risk_adjusted_ltv <= MAX_LTV,
+            computation.pending && computation.request_nonce == request_nonce,
+            ArciLendError::ComputationMismatch
+        );
+        require!(clock.unix_timestamp <= computation.deadline, ArciLendError::ComputationExpired);
+        require!(
+            (MIN_LTV..=MAX_LTV).contains(&risk_adjusted_ltv),
             ArciLendError::InvalidCreditScore
         );
 
         user_account.encrypted_credit_score = encrypted_score;
         user_account.risk_adjusted_ltv = risk_adjusted_ltv;
+        user_account.credit_score_expires_at = clock.unix_timestamp + CREDIT_SCORE_VALIDITY;
+        computation.pending = false;
+
+        // Fulfilled, so the request fee becomes claimable by the MPC operator
+        lending_pool.credit_score_fees_escrowed -= computation.fee_paid;
+        lending_pool.credit_score_fees_claimable += computation.fee_paid;
+        computation.fee_paid = 0;
 
         msg!("✅ Credit score updated via MPC!");
         msg!("Risk-adjusted LTV: {}%", risk_adjusted_ltv / 100);
@@ -124,42 +489,335 @@ pub mod arcilend {
         Ok(())
     }
 
-    pub fn borrow(ctx: Context<Borrow>, amount: u64) -> Result<()>{
+    /// Asks the MPC cluster to re-encrypt the caller's score to `recipient_key`,
+    /// an x25519 key held by the borrower or a lender they choose
+    pub fn request_score_disclosure(ctx: Context<RequestScoreDisclosure>, recipient_key: [u8; 32]) -> Result<()> {
+        let user_account = &ctx.accounts.user_account;
+        let disclosure = &mut ctx.accounts.score_disclosure;
+        let clock = Clock::get()?;
+
+        require!(
+            user_account.encrypted_credit_score != [0u8; 32],
+            ArciLendError::NoCreditScore
+        );
+
+        disclosure.user = user_account.owner;
+        disclosure.recipient_key = recipient_key;
+        disclosure.source_score = user_account.encrypted_credit_score;
+        disclosure.ciphertext = [0u8; 32];
+        disclosure.nonce = 0;
+        disclosure.pending = true;
+        disclosure.requested_at = clock.unix_timestamp;
+        disclosure.bump = ctx.bumps.score_disclosure;
+
+        emit!(
+            ScoreDisclosureRequested {
+                user: disclosure.user,
+                recipient_key,
+                source_score: disclosure.source_score,
+                timestamp: clock.unix_timestamp,
+            }
+        );
+
+        msg!("🔐 Score disclosure requested");
+
+        Ok(())
+    }
+
+    /// Stores the re-encrypted score once enough MPC nodes attest to it
+    pub fn fulfill_score_disclosure(
+        ctx: Context<FulfillScoreDisclosure>,
+        ciphertext: [u8; 32],
+        nonce: u128,
+        expiry: i64
+    ) -> Result<()> {
+        let lending_pool = &ctx.accounts.lending_pool;
+        let disclosure = &mut ctx.accounts.score_disclosure;
+        let clock = Clock::get()?;
+
+        require!(disclosure.pending, ArciLendError::ComputationMismatch);
+        require!(clock.unix_timestamp <= expiry, ArciLendError::AttestationExpired);
+
+        let message = score_disclosure_message(
+            &disclosure.user,
+            &disclosure.recipient_key,
+            &disclosure.source_score,
+            &ciphertext,
+            nonce,
+            expiry,
+        );
+        let attestations = lending_pool.count_mpc_attestations(&ctx.accounts.instructions, &message, clock.unix_timestamp)?;
+
+        require!(
+            attestations >= lending_pool.mpc_threshold as usize,
+            ArciLendError::InsufficientAttestations
+        );
+
+        disclosure.ciphertext = ciphertext;
+        disclosure.nonce = nonce;
+        disclosure.pending = false;
+
+        emit!(
+            ScoreDisclosed {
+                user: disclosure.user,
+                recipient_key: disclosure.recipient_key,
+                ciphertext,
+                nonce,
+            }
+        );
+
+        msg!("✅ Score re-encrypted for disclosure");
+
+        Ok(())
+    }
+
+    /// CPI entry point for other programs to gate on "score >= threshold" without
+    /// learning the score. MPC nodes attest the comparison against the user's
+    /// current encrypted score; the verified result is returned as return data.
+    pub fn verify_credit_threshold(
+        ctx: Context<VerifyCreditThreshold>,
+        threshold: u16,
+        meets_threshold: bool,
+        expiry: i64
+    ) -> Result<bool> {
+        let lending_pool = &ctx.accounts.lending_pool;
         let user_account = &ctx.accounts.user_account;
+        let clock = Clock::get()?;
+
+        require!(
+            user_account.encrypted_credit_score != [0u8; 32],
+            ArciLendError::NoCreditScore
+        );
+        require!(
+            clock.unix_timestamp <= user_account.credit_score_expires_at,
+            ArciLendError::CreditScoreExpired
+        );
+        require!(clock.unix_timestamp <= expiry, ArciLendError::AttestationExpired);
+
+        let message = score_threshold_message(
+            &user_account.owner,
+            &user_account.encrypted_credit_score,
+            threshold,
+            meets_threshold,
+            expiry,
+        );
+        let attestations = lending_pool.count_mpc_attestations(&ctx.accounts.instructions, &message, clock.unix_timestamp)?;
+
+        require!(
+            attestations >= lending_pool.mpc_threshold as usize,
+            ArciLendError::InsufficientAttestations
+        );
+
+        msg!("Credit score threshold {}: {}", threshold, meets_threshold);
+
+        Ok(meets_threshold)
+    }
+
+    pub fn claim_credit_score_fees(ctx: Context<ClaimCreditScoreFees>) -> Result<()> {
         let lending_pool = &mut ctx.accounts.lending_pool;
-        let loan = &mut ctx.accounts.loan;
+        let amount = lending_pool.credit_score_fees_claimable;
+
+        require!(amount > 0, ArciLendError::InsufficientBalance);
+
+        **lending_pool.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.mpc_operator.to_account_info().try_borrow_mut_lamports()? += amount;
+
+        lending_pool.credit_score_fees_claimable = 0;
+
+        msg!("Claimed {} lamports of credit score fees", amount);
+
+        Ok(())
+    }
+
+    pub fn delegate_credit(ctx: Context<DelegateCredit>, cap: u64, expiry: i64) -> Result<()> {
+        let user_account = &mut ctx.accounts.user_account;
+        let credit_delegation = &mut ctx.accounts.credit_delegation;
         let clock = Clock::get()?;
 
-        let collateral_value = user_account.collateral_deposited;
-        let max_borrow = (collateral_value as u128 * user_account.risk_adjusted_ltv as u128) / BASIS_POINTS as u128;
+        require!(cap > 0, ArciLendError::InsufficientBalance);
+        require!(expiry > clock.unix_timestamp, ArciLendError::DelegationExpired);
+        require!(
+            ctx.accounts.delegatee.key() != ctx.accounts.delegator.key(),
+            ArciLendError::InvalidDelegation
+        );
 
+        // Delegated caps are reserved against the delegator's own borrowing capacity
+        let max_borrow = user_account.collateral_deposited as u128
+            * user_account.effective_ltv(clock.unix_timestamp) as u128
+            / BASIS_POINTS as u128;
         require!(
-            amount <= max_borrow as u64,
+            (user_account.committed_credit() + cap) as u128 <= max_borrow,
             ArciLendError::ExceedsRiskAdjustedLTV
         );
 
-        let new_total_borrowed = user_account.amount_borrowed + amount;
-        let thereshold_value = (collateral_value as u128 * lending_pool.collateral_ratio as u128) / BASIS_POINTS as u128;
-        
+        credit_delegation.delegator = ctx.accounts.delegator.key();
+        credit_delegation.delegatee = ctx.accounts.delegatee.key();
+        credit_delegation.cap = cap;
+        credit_delegation.expiry = expiry;
+        credit_delegation.used = 0;
+        credit_delegation.bump = ctx.bumps.credit_delegation;
+
+        user_account.credit_delegated += cap;
+        user_account.last_update = clock.unix_timestamp;
+
+        msg!("Delegated {} lamports of credit to {}", cap, credit_delegation.delegatee);
+
+        Ok(())
+    }
+
+    pub fn revoke_credit_delegation(ctx: Context<RevokeCreditDelegation>) -> Result<()> {
+        let user_account = &mut ctx.accounts.user_account;
+        let credit_delegation = &ctx.accounts.credit_delegation;
+
+        require!(credit_delegation.used == 0, ArciLendError::DelegatedDebtOutstanding);
+
+        user_account.credit_delegated = user_account.credit_delegated.saturating_sub(credit_delegation.cap);
+        user_account.last_update = Clock::get()?.unix_timestamp;
+
+        msg!("Revoked credit delegation to {}", credit_delegation.delegatee);
+
+        Ok(())
+    }
+
+    pub fn borrow(ctx: Context<Borrow>, amount: u64, guaranteed_amount: u64) -> Result<()>{
+        let user_account = &ctx.accounts.user_account;
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let loan = &mut ctx.accounts.loan;
+        let clock = Clock::get()?;
+
+        require!(
+            lending_pool.total_borrowed + amount <= lending_pool.borrow_cap,
+            ArciLendError::BorrowCapExceeded
+        );
         require!(
-            new_total_borrowed <= thereshold_value as u64,
-            ArciLendError::Undercollateralized
+            user_account.amount_borrowed + user_account.credit_line_drawn + amount <= lending_pool.max_user_borrow,
+            ArciLendError::UserBorrowLimitExceeded
         );
 
+        let risk_adjusted_ltv;
+        let max_borrow;
+        let delegation_key;
+        let isolated_mint;
 
-        let base_rate = lending_pool.get_curent_interest_rate();
-        let risk_premium = if user_account.risk_adjusted_ltv > 7000 {
-            0   // good credit no premium
+        if let Some(credit_delegation) = ctx.accounts.credit_delegation.as_mut() {
+            // Borrowing against someone else's capacity: their collateral backs the debt
+            let delegator_account = ctx
+                .accounts
+                .delegator_account
+                .as_mut()
+                .ok_or(ArciLendError::InvalidDelegation)?;
+
+            require!(guaranteed_amount == 0, ArciLendError::InvalidGuarantee);
+
+            require!(
+                delegator_account.owner == credit_delegation.delegator,
+                ArciLendError::InvalidDelegation
+            );
+            require!(
+                clock.unix_timestamp <= credit_delegation.expiry,
+                ArciLendError::DelegationExpired
+            );
+            require!(
+                credit_delegation.used + amount <= credit_delegation.cap,
+                ArciLendError::ExceedsDelegatedCredit
+            );
+
+            credit_delegation.used += amount;
+            delegator_account.delegated_debt += amount;
+
+            // The delegator's collateral backs the debt, so their isolated asset's ceiling applies
+            isolated_mint = lending_pool.reserve_isolated_debt(delegator_account, amount)?;
+
+            risk_adjusted_ltv = delegator_account.effective_ltv(clock.unix_timestamp);
+            max_borrow = credit_delegation.cap as u128;
+            delegation_key = credit_delegation.key();
+        } else {
+            let basket = lending_pool.basket_value(user_account, &mut ctx.remaining_accounts.iter(), &clock)?;
+            risk_adjusted_ltv = user_account.effective_ltv(clock.unix_timestamp);
+            let collateral_value = user_account.native_collateral() + basket.collateral_value;
+            max_borrow = lending_pool.borrowing_power(user_account, &basket, clock.unix_timestamp)?;
+
+            require!(guaranteed_amount <= amount, ArciLendError::InvalidGuarantee);
+
+            // The guaranteed portion is backed by the guarantor, so only the
+            // remainder has to fit the borrower's own capacity. Capacity
+            // delegated or pledged to others is not available to the borrower
+            let own_amount = amount - guaranteed_amount;
+            require!(
+                (own_amount + user_account.credit_delegated + user_account.amount_guaranteed) as u128 <= max_borrow,
+                ArciLendError::ExceedsRiskAdjustedLTV
+            );
+
+            // The loan must not open liquidatable: SOL, the guarantee and the
+            // basket together have to clear the liquidation threshold on all
+            // the debt, not just the borrower's own share
+            let (liquidation_threshold, _, basket_liquidation_value) = lending_pool.liquidation_terms(user_account, &basket)?;
+            let debt = user_account.amount_borrowed + user_account.delegated_debt + amount;
+            require!(
+                (user_account.native_collateral() + guaranteed_amount + basket_liquidation_value) as u128 * BASIS_POINTS as u128
+                    >= debt as u128 * liquidation_threshold as u128,
+                ArciLendError::Undercollateralized
+            );
+
+            let new_total_borrowed = user_account.amount_borrowed + own_amount;
+            let thereshold_value = (collateral_value as u128 * lending_pool.collateral_ratio as u128) / BASIS_POINTS as u128;
+
+            require!(
+                new_total_borrowed <= thereshold_value as u64,
+                ArciLendError::Undercollateralized
+            );
+
+            isolated_mint = lending_pool.reserve_isolated_debt(user_account, amount)?;
+
+            delegation_key = Pubkey::default();
+        }
+
+        let guarantor_key = if guaranteed_amount > 0 {
+            let guarantor = ctx.accounts.guarantor.as_ref().ok_or(ArciLendError::InvalidGuarantee)?;
+            let guarantor_account = ctx
+                .accounts
+                .guarantor_account
+                .as_mut()
+                .ok_or(ArciLendError::InvalidGuarantee)?;
+
+            require!(
+                guarantor.key() != ctx.accounts.borrower.key()
+                    && guarantor_account.owner == guarantor.key(),
+                ArciLendError::InvalidGuarantee
+            );
+
+            let guarantor_capacity = guarantor_account.collateral_deposited as u128
+                * guarantor_account.effective_ltv(clock.unix_timestamp) as u128
+                / BASIS_POINTS as u128;
+            require!(
+                (guarantor_account.committed_credit() + guaranteed_amount) as u128 <= guarantor_capacity,
+                ArciLendError::ExceedsRiskAdjustedLTV
+            );
+
+            guarantor_account.amount_guaranteed += guaranteed_amount;
+            guarantor_account.last_update = clock.unix_timestamp;
+
+            guarantor.key()
         } else {
-            200 // + 2% for lower credit score
+            Pubkey::default()
         };
 
-        let personalized_rate = base_rate + risk_premium;
+
+        let tier = lending_pool.risk_tier(risk_adjusted_ltv);
+
+        require!(amount <= tier.max_loan, ArciLendError::ExceedsTierLoanLimit);
+
+        let base_rate = lending_pool.get_curent_interest_rate();
+        let personalized_rate = base_rate + tier.premium_bps;
         
         // initialize loan
         loan.borrower = ctx.accounts.borrower.key();
         loan.user_account = user_account.key();
-        loan.collateral_amount = user_account.collateral_deposited;
+        loan.collateral_amount = if delegation_key == Pubkey::default() {
+            user_account.collateral_deposited
+        } else {
+            0
+        };
         loan.borrowed_amount = amount;
         loan.interest_rate = personalized_rate;
         loan.start_time = clock.unix_timestamp;
@@ -167,6 +825,12 @@ pub mod arcilend {
         loan.accrued_interest = 0;
         loan.is_liquidated = false;
         loan.bump = ctx.bumps.loan;
+        loan.delegation = delegation_key;
+        loan.guarantor = guarantor_key;
+        loan.guaranteed_amount = guaranteed_amount;
+        loan.isolated_mint = isolated_mint;
+        loan.due_at = clock.unix_timestamp + lending_pool.loan_term;
+        loan.late_payment_recorded = false;
 
         // Transfer borrowed amount to user
         **lending_pool.to_account_info().try_borrow_mut_lamports()? -= amount;
@@ -174,10 +838,20 @@ pub mod arcilend {
         
         let user_account = &mut ctx.accounts.user_account;
 
-        user_account.amount_borrowed += amount;
         user_account.loan_count += 1;
         user_account.last_update = clock.unix_timestamp;
 
+        // Delegated debt is carried by the delegator's account, not the borrower's
+        if delegation_key == Pubkey::default() {
+            user_account.amount_borrowed += amount;
+
+            let utilization_bps = (user_account.amount_borrowed as u128 * BASIS_POINTS as u128)
+                .checked_div(max_borrow)
+                .unwrap_or(0)
+                .min(u16::MAX as u128) as u16;
+            user_account.max_utilization_bps = user_account.max_utilization_bps.max(utilization_bps);
+        }
+
         lending_pool.total_borrowed += amount;
         lending_pool.calculate_utilization();
 
@@ -188,11 +862,14 @@ pub mod arcilend {
     }
 
     pub fn repay(ctx: Context<Repay>, amount: u64) -> Result<()>{
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
         let user_account = &mut ctx.accounts.user_account;
         let lending_pool = &mut ctx.accounts.lending_pool;
         let loan = &mut ctx.accounts.loan;
         let clock = Clock::get()?;
 
+        require!(!loan.is_liquidated, ArciLendError::AlreadyLiquidated);
+
         loan.accrue_interest(clock.unix_timestamp);
 
         let total_owed = loan.total_owed();
@@ -200,51 +877,74 @@ pub mod arcilend {
 
         require!(repay_amount > 0, ArciLendError::InsufficientBalance);
 
-        **ctx.accounts.borrower.to_account_info().try_borrow_mut_lamports()? -= repay_amount;
-        **lending_pool.to_account_info().try_borrow_mut_lamports()? += repay_amount;
-
-        if repay_amount >= loan.accrued_interest {
-            let principal_payment = repay_amount - loan.accrued_interest;
-            loan.accrued_interest = 0;
-            loan.borrowed_amount -= principal_payment;
-        } else {
-            loan.accrued_interest -= repay_amount;
-        }
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.borrower.to_account_info(),
+                to: lending_pool_info,
+            },
+        );
+        system_program::transfer(cpi_context, repay_amount)?;
 
-        user_account.amount_borrowed = user_account.amount_borrowed.saturating_sub(repay_amount);
+        let principal_payment = loan.apply_repayment(repay_amount);
+        release_delegated_debt(
+            loan,
+            principal_payment,
+            ctx.accounts.credit_delegation.as_mut(),
+            ctx.accounts.delegator_account.as_mut(),
+        )?;
+        release_guarantee(loan, ctx.accounts.guarantor_account.as_mut())?;
+        lending_pool.release_isolated_debt(loan, principal_payment);
 
-        if loan.borrowed_amount == 0 {
-            user_account.successful_repayments += 1;
+        // Only principal counts as borrowed; delegated debt sits with the delegator
+        if loan.delegation == Pubkey::default() {
+            user_account.amount_borrowed = user_account.amount_borrowed.saturating_sub(principal_payment);
         }
+        user_account.record_repayment(loan, repay_amount, clock.unix_timestamp);
+        ctx.accounts.reputation.record_repayment(loan, repay_amount, clock.unix_timestamp);
 
-        lending_pool.total_borrowed = lending_pool.total_borrowed.saturating_sub(repay_amount);
+        lending_pool.total_borrowed = lending_pool.total_borrowed.saturating_sub(principal_payment);
         lending_pool.calculate_utilization();
 
-        msg!("Repaid {} lamports", repay_amount);
+        // Once the loan is paid off, interest included, and nothing else is
+        // committed, a custodied stake account goes back to the borrower.
+        // Anyone else settling the debt leaves it in custody for the borrower
+        // to withdraw.
+        if loan.total_owed() == 0
+            && user_account.committed_credit() == 0
+            && user_account.stake_collateral > 0
+            && ctx.accounts.borrower.key() == loan.borrower
+        {
+            let stake_collateral = ctx.accounts.stake_collateral.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            let stake_account = ctx.accounts.stake_account.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            let clock_sysvar = ctx.accounts.clock.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            let stake_program = ctx.accounts.stake_program.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            require_keys_eq!(stake_account.key(), stake_collateral.stake_account, ArciLendError::InvalidStakeAccount);
 
-        Ok(())
-    }
+            authorize_stake(
+                stake_program.to_account_info(),
+                stake_account.to_account_info(),
+                lending_pool.to_account_info(),
+                ctx.accounts.borrower.to_account_info(),
+                clock_sysvar.to_account_info(),
+                &[&[LENDING_POOL_SEED, &[lending_pool.bump]]],
+            )?;
+            stake_collateral.close(ctx.accounts.borrower.to_account_info())?;
 
-    pub fn withdraw_collateral(ctx: Context<WithdrawCollateral>, amount: u64) -> Result<()>{
-        let user_account = &mut ctx.accounts.user_account;
-        let lending_pool = &mut ctx.accounts.lending_pool;
-        
-        require!(user_account.amount_borrowed == 0, ArciLendError::ActiveLoansExist);
-        require!(amount <= user_account.collateral_deposited, ArciLendError::InsufficientBalance);
-        
-        **lending_pool.to_account_info().try_borrow_mut_lamports()? -= amount;
-        **user_account.to_account_info().try_borrow_mut_lamports()? += amount;
+            lending_pool.total_stake_collateral -= user_account.stake_collateral;
+            user_account.stake_collateral = 0;
+            user_account.last_update = clock.unix_timestamp;
 
-        user_account.collateral_deposited -= amount;
-        lending_pool.total_deposits -= amount;
-        lending_pool.calculate_utilization();
+            msg!("Stake account {} returned", stake_account.key());
+        }
 
-        msg!("Withdrew {} lamports", amount);
+        msg!("Repaid {} lamports", repay_amount);
 
         Ok(())
     }
 
-    pub fn liquidate(ctx: Context<Liquidate>) -> Result<()>{
+    pub fn repay_for(ctx: Context<RepayFor>, amount: u64) -> Result<()> {
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
         let user_account = &mut ctx.accounts.user_account;
         let lending_pool = &mut ctx.accounts.lending_pool;
         let loan = &mut ctx.accounts.loan;
@@ -254,334 +954,3585 @@ pub mod arcilend {
 
         loan.accrue_interest(clock.unix_timestamp);
 
-        let collateral_price = 1_000_000_000;
-        
-        require!(
-            user_account.is_liquidatable(collateral_price, lending_pool.liquidation_threshold),
-            ArciLendError::LoanNotLiquidatable
+        let total_owed = loan.total_owed();
+        let repay_amount = amount.min(total_owed);
+
+        require!(repay_amount > 0, ArciLendError::InsufficientBalance);
+
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: lending_pool_info,
+            },
         );
+        system_program::transfer(cpi_context, repay_amount)?;
 
-        let total_dept = loan.total_owed();
-        let collateral_to_seize = loan.collateral_amount;
-        let bonus = (collateral_to_seize as u128 * LIQUIDATION_BONUS as u128) / BASIS_POINTS as u128;
-        let total_reward = collateral_to_seize + bonus as u64;
+        let principal_payment = loan.apply_repayment(repay_amount);
+        release_delegated_debt(
+            loan,
+            principal_payment,
+            ctx.accounts.credit_delegation.as_mut(),
+            ctx.accounts.delegator_account.as_mut(),
+        )?;
+        release_guarantee(loan, ctx.accounts.guarantor_account.as_mut())?;
+        lending_pool.release_isolated_debt(loan, principal_payment);
 
-        **ctx.accounts.liquidator.to_account_info().try_borrow_mut_lamports()? -= total_dept;
-        **lending_pool.to_account_info().try_borrow_mut_lamports()? += total_dept;
+        // Credit history always belongs to the borrower, not whoever paid
+        // Only principal counts as borrowed; delegated debt sits with the delegator
+        if loan.delegation == Pubkey::default() {
+            user_account.amount_borrowed = user_account.amount_borrowed.saturating_sub(principal_payment);
+        }
+        user_account.record_repayment(loan, repay_amount, clock.unix_timestamp);
+        ctx.accounts.reputation.record_repayment(loan, repay_amount, clock.unix_timestamp);
 
-        **ctx.accounts.liquidator.to_account_info().try_borrow_mut_lamports()? -= total_reward;
-        **lending_pool.to_account_info().try_borrow_mut_lamports()? += total_reward;
+        lending_pool.total_borrowed = lending_pool.total_borrowed.saturating_sub(principal_payment);
+        lending_pool.calculate_utilization();
 
-        loan.is_liquidated = true;
-        user_account.amount_borrowed -= loan.borrowed_amount;
-        user_account.collateral_deposited -= collateral_to_seize;
-        user_account.defaults += 1;
+        msg!("Repaid {} lamports on behalf of {}", repay_amount, loan.borrower);
+        msg!("Payer: {}", ctx.accounts.payer.key());
 
-        lending_pool.total_borrowed -= loan.borrowed_amount;
-        lending_pool.total_deposits -= collateral_to_seize;
+        Ok(())
+    }
+
+    pub fn add_loan_collateral(ctx: Context<AddLoanCollateral>, amount: u64) -> Result<()> {
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
+        let user_account = &mut ctx.accounts.user_account;
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let loan = &mut ctx.accounts.loan;
+
+        require!(amount > 0, ArciLendError::InsufficientBalance);
+        require!(!loan.is_liquidated, ArciLendError::AlreadyLiquidated);
+        require!(loan.total_owed() > 0, ArciLendError::LoanNotActive);
+        lending_pool.check_supply_cap(amount)?;
+
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.borrower.to_account_info(),
+                to: lending_pool_info,
+            },
+        );
+        system_program::transfer(cpi_context, amount)?;
+
+        loan.collateral_amount += amount;
+        user_account.collateral_deposited += amount;
+        user_account.last_update = Clock::get()?.unix_timestamp;
+        lending_pool.total_deposits += amount;
         lending_pool.calculate_utilization();
 
-        msg!("Liquidation successful!");
-        msg!("Debt: {}, seized: {}, Bonus: {}", total_dept, collateral_to_seize, bonus);
+        msg!("Added {} lamports of collateral to loan", amount);
+        msg!("Loan collateral: {}", loan.collateral_amount);
 
         Ok(())
     }
 
-    pub fn accure_interest(ctx: Context<AccrueInterest>) -> Result<()>{
+    pub fn deleverage(ctx: Context<Deleverage>, amount: u64) -> Result<()> {
+        let user_account = &mut ctx.accounts.user_account;
+        let lending_pool = &mut ctx.accounts.lending_pool;
         let loan = &mut ctx.accounts.loan;
         let clock = Clock::get()?;
 
+        require!(!loan.is_liquidated, ArciLendError::AlreadyLiquidated);
+        require!(loan.delegation == Pubkey::default(), ArciLendError::DelegatedLoan);
+
         loan.accrue_interest(clock.unix_timestamp);
 
-        msg!("Interest accrued: {} lamports", loan.accrued_interest);
+        let repay_amount = amount.min(loan.total_owed());
+        require!(repay_amount > 0, ArciLendError::InsufficientBalance);
+
+        // Collateral already sits in the pool, so repaying with it is pure accounting
+        let collateral_price = lending_pool.collateral_price(&ctx.accounts.oracle_feed)?;
+        let collateral_used = collateral_for_value(repay_amount, collateral_price);
+        let fee = (collateral_used as u128 * DELEVERAGE_FEE_BPS as u128 / BASIS_POINTS as u128) as u64;
+        let collateral_burned = collateral_used + fee;
+
+        require!(
+            collateral_burned <= loan.collateral_amount
+                && collateral_burned <= user_account.collateral_deposited,
+            ArciLendError::InsufficientCollateral
+        );
+
+        let principal_payment = loan.apply_repayment(repay_amount);
+        loan.collateral_amount -= collateral_burned;
+        release_guarantee(loan, ctx.accounts.guarantor_account.as_mut())?;
+        lending_pool.release_isolated_debt(loan, principal_payment);
+
+        user_account.collateral_deposited -= collateral_burned;
+        user_account.amount_borrowed = user_account.amount_borrowed.saturating_sub(principal_payment);
+        user_account.last_update = clock.unix_timestamp;
+
+        // Credit delegated to others stays reserved against the collateral left
+        if user_account.credit_delegated > 0 {
+            let remaining_capacity = user_account.collateral_deposited as u128
+                * user_account.effective_ltv(clock.unix_timestamp) as u128
+                / BASIS_POINTS as u128;
+            require!(
+                user_account.committed_credit() as u128 <= remaining_capacity,
+                ArciLendError::ExceedsRiskAdjustedLTV
+            );
+        }
+        user_account.record_repayment(loan, repay_amount, clock.unix_timestamp);
+        ctx.accounts.reputation.record_repayment(loan, repay_amount, clock.unix_timestamp);
+
+        lending_pool.total_deposits -= collateral_burned;
+        lending_pool.total_borrowed = lending_pool.total_borrowed.saturating_sub(principal_payment);
+        lending_pool.total_fees += fee;
+        lending_pool.calculate_utilization();
+
+        msg!("Deleveraged {} lamports of debt", repay_amount);
+        msg!("Collateral burned: {}, fee: {}", collateral_burned, fee);
 
         Ok(())
     }
-}
 
-/// ---- Accounts ----
+    pub fn withdraw_collateral(ctx: Context<WithdrawCollateral>, amount: u64) -> Result<()>{
+        let user_account = &mut ctx.accounts.user_account;
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let clock = Clock::get()?;
+        
+        require!(amount <= user_account.collateral_deposited, ArciLendError::InsufficientBalance);
 
-#[derive(Accounts)]
-pub struct InitializePool<'info> {
-    #[account(mut)]
-    pub authority: Signer<'info>,
-    #[account(
-        init,
-        payer = authority,
-        space = 8 + LendingPool::INIT_SPACE,
-        seeds = [LENDING_POOL_SEED],
-        bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
-    /// CHECK: This is the authorized Arcium MPC node public key that will provide credit score updates
-    pub arcium_mpc_pubkey: AccountInfo<'info>,
-    /// CHECK: This is the oracle feed address (Pyth/Switchboard) for price data
-    pub oracle_feed: AccountInfo<'info>,
-    pub system_program: Program<'info, System>
-}
+        // With debt outstanding, the remaining collateral must still cover the
+        // loan (including accrued interest) at the pool's collateral ratio
+        if user_account.amount_borrowed > 0 && ctx.accounts.loan.is_none() {
+            return err!(ArciLendError::ActiveLoansExist);
+        }
+        if let Some(loan) = ctx.accounts.loan.as_mut() {
+            if loan.delegation == Pubkey::default() && !loan.is_liquidated {
+                loan.accrue_interest(clock.unix_timestamp);
 
-#[derive(Accounts)]
-pub struct DepositCollateral<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
-    
-    #[account(
-        mut,
-        seeds = [LENDING_POOL_SEED],
-        bump = lending_pool.bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+                if loan.total_owed() > 0 {
+                    let collateral_price = lending_pool.collateral_price(&ctx.accounts.oracle_feed)?;
+                    let remaining_value = collateral_value(user_account.collateral_deposited - amount, collateral_price);
 
-    #[account(
-        init_if_needed,
-        payer = user,
-        space = 8 + UserAccount::INIT_SPACE,
-        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
-        bump
-    )]
-    pub user_account: Account<'info, UserAccount>,
+                    require!(
+                        is_withdrawal_healthy(remaining_value, loan.total_owed(), lending_pool.collateral_ratio),
+                        ArciLendError::HealthFactorTooLow
+                    );
+                }
+            }
+        }
 
-    pub system_program: Program<'info, System>
+        // Credit delegated or guaranteed for others stays reserved against the remaining collateral
+        if user_account.credit_delegated > 0 || user_account.amount_guaranteed > 0 {
+            let remaining_capacity = (user_account.collateral_deposited - amount) as u128
+                * user_account.effective_ltv(clock.unix_timestamp) as u128
+                / BASIS_POINTS as u128;
+            require!(
+                user_account.committed_credit() as u128 <= remaining_capacity,
+                ArciLendError::ExceedsRiskAdjustedLTV
+            );
+        }
+        
+        **lending_pool.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.user.to_account_info().try_borrow_mut_lamports()? += amount;
 
-}
+        user_account.withdraw_collateral(amount, ctx.accounts.loan.as_deref_mut());
+        lending_pool.total_deposits -= amount;
+        lending_pool.calculate_utilization();
 
-#[derive(Accounts)]
-pub struct RequestCreditScore<'info> {
-    #[account(
-        seeds = [LENDING_POOL_SEED],
-        bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+        msg!("Withdrew {} lamports", amount);
 
-    #[account(
-        mut,
-        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
-        bump = user_account.bump,
-        constraint = user_account.owner == user.key()
-    )]
-    pub user_account: Account<'info, UserAccount>,
+        Ok(())
+    }
 
-    pub user: Signer<'info>,
-}
+    pub fn set_collateral_asset(
+        ctx: Context<SetCollateralAsset>,
+        oracle_feed: Pubkey,
+        ltv_bps: u16,
+        liquidation_threshold_bps: u16,
+    ) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
 
-#[derive(Accounts)]
-pub struct UpdateCreditScore<'info> {
-    #[account(
-        seeds = [LENDING_POOL_SEED],
-        bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+        lending_pool.validate_mint_extensions(&ctx.accounts.collateral_mint.to_account_info())?;
+        require!(
+            ltv_bps > 0 && ltv_bps <= liquidation_threshold_bps && liquidation_threshold_bps <= BASIS_POINTS,
+            ArciLendError::InvalidCollateralAsset
+        );
 
-    #[account(
-        mut,
-        seeds = [USER_ACCOUNT_SEED, user_account.key().as_ref()],
-        bump = user_account.bump,
-    )]
-    pub user_account: Account<'info, UserAccount>,
+        let asset = CollateralAsset {
+            mint: ctx.accounts.collateral_mint.key(),
+            oracle_feed,
+            decimals: ctx.accounts.collateral_mint.decimals,
+            ltv_bps,
+            liquidation_threshold_bps,
+            supply_cap: u64::MAX,
+            ..Default::default()
+        };
 
-    pub mpc_authority: Signer<'info>,
-}
+        let count = lending_pool.collateral_asset_count as usize;
+        match lending_pool.collateral_assets[..count].iter().position(|a| a.mint == asset.mint) {
+            Some(index) => {
+                // Isolation, e-mode, stake pool and supply cap settings and the
+                // tracked debt and supply survive a re-listing
+                let existing = &mut lending_pool.collateral_assets[index];
+                existing.oracle_feed = asset.oracle_feed;
+                existing.decimals = asset.decimals;
+                existing.ltv_bps = asset.ltv_bps;
+                existing.liquidation_threshold_bps = asset.liquidation_threshold_bps;
+            }
+            None => {
+                require!(count < MAX_COLLATERAL_ASSETS, ArciLendError::InvalidCollateralAsset);
+                lending_pool.collateral_assets[count] = asset;
+                lending_pool.collateral_asset_count += 1;
+            }
+        }
 
-#[derive(Accounts)]
-pub struct Borrow<'info> {
-    #[account(mut)]
-    pub borrower: Signer<'info>,
+        msg!("Collateral asset {} set: LTV {}bps, liquidation {}bps", asset.mint, ltv_bps, liquidation_threshold_bps);
 
-    #[account(
-        seeds = [LENDING_POOL_SEED],
-        bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [USER_ACCOUNT_SEED, borrower.key().as_ref()],
-        bump = user_account.bump,
-        constraint = user_account.owner == borrower.key()
-    )]
-    pub user_account: Account<'info, UserAccount>,
+    pub fn set_transfer_hook_allowlist(ctx: Context<SetTransferHookAllowlist>, programs: Vec<Pubkey>) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
 
-    #[account(
-        init,
-        payer = borrower,
-        space = Loan::INIT_SPACE,
-        seeds = [LOAN_SEED, borrower.key().as_ref()],
-        bump
-    )]
-    pub loan: Account<'info, Loan>,
+        require!(
+            programs.len() <= MAX_TRANSFER_HOOK_PROGRAMS,
+            ArciLendError::TooManyTransferHookPrograms
+        );
 
-    pub system_program: Program<'info, System>
-}
+        lending_pool.transfer_hook_allowlist = [Pubkey::default(); MAX_TRANSFER_HOOK_PROGRAMS];
+        lending_pool.transfer_hook_allowlist[..programs.len()].copy_from_slice(&programs);
+        lending_pool.transfer_hook_count = programs.len() as u8;
 
-#[derive(Accounts)]
-pub struct Repay<'info> {
-    #[account(mut)]
-    pub borrower: Signer<'info>,
+        msg!("Transfer hook allowlist updated: {} programs", programs.len());
 
-    #[account(
-        seeds = [LENDING_POOL_SEED],
-        bump = lending_pool.bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [USER_ACCOUNT_SEED, loan.borrower.as_ref()],
-        bump = user_account.bump,
-    )]
-    pub user_account: Account<'info, UserAccount>,
+    /// Pool programs whose `Reputation` records fresh accounts may import
+    pub fn set_reputation_sources(ctx: Context<SetReputationSources>, programs: Vec<Pubkey>) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
 
-    #[account(
-        mut,
-        seeds = [LOAN_SEED, loan.borrower.key().as_ref()],
-        bump = loan.bump
-    )]
-    pub loan: Account<'info, Loan>,
+        require!(
+            programs.len() <= MAX_REPUTATION_SOURCES,
+            ArciLendError::TooManyReputationSources
+        );
+        require!(!programs.contains(&crate::ID), ArciLendError::InvalidReputationSource);
 
-    pub system_program: Program<'info, System>
-}
+        lending_pool.reputation_sources = [Pubkey::default(); MAX_REPUTATION_SOURCES];
+        lending_pool.reputation_sources[..programs.len()].copy_from_slice(&programs);
+        lending_pool.reputation_source_count = programs.len() as u8;
 
-#[derive(Accounts)]
-pub struct WithdrawCollateral<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+        msg!("Reputation sources updated: {} programs", programs.len());
 
-    #[account(
-        seeds = [LENDING_POOL_SEED],
-        bump = lending_pool.bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
-        bump = user_account.bump,
-        constraint = user_account.owner == user.key()
-    )]
-    pub user_account: Account<'info, UserAccount>,
+    /// Prices a listed asset through an SPL stake pool's exchange rate. The
+    /// asset's oracle feed, if any, then only caps the price.
+    pub fn set_asset_stake_pool(ctx: Context<SetAssetStakePool>, stake_pool: Pubkey) -> Result<()> {
+        let mint = ctx.accounts.collateral_mint.key();
 
-    pub system_program: Program<'info, System>
+        ctx.accounts.lending_pool.collateral_asset_mut(&mint)?.stake_pool = stake_pool;
 
-}
+        msg!("Collateral asset {} priced via stake pool {}", mint, stake_pool);
 
-#[derive(Accounts)]
-pub struct Liquidate<'info> {
-    #[account(
-        mut,
-        seeds = [LENDING_POOL_SEED],
-        bump = lending_pool.bump
-    )]
-    pub lending_pool: Account<'info, LendingPool>,
+        Ok(())
+    }
 
-    #[account(
-        mut,
-        seeds = [USER_ACCOUNT_SEED, loan.borrower.as_ref()],
-        bump = user_account.bump,
-    )]
-    pub user_account: Account<'info, UserAccount>,
+    /// Caps basket deposits of a listed asset, in the asset's base units
+    pub fn set_asset_supply_cap(ctx: Context<SetAssetSupplyCap>, supply_cap: u64) -> Result<()> {
+        let mint = ctx.accounts.collateral_mint.key();
 
-    #[account(
-        mut,
-        seeds = [LOAN_SEED, loan.borrower.as_ref()],
-        bump = loan.bump
-    )]
-    pub loan: Account<'info, Loan>,
+        ctx.accounts.lending_pool.collateral_asset_mut(&mint)?.supply_cap = supply_cap;
 
-    #[account(mut)]
-    pub liquidator: Signer<'info>,
+        msg!("Collateral asset {} supply cap: {}", mint, supply_cap);
 
-    pub system_program: Program<'info, System>
+        Ok(())
+    }
+
+    pub fn set_isolation_mode(ctx: Context<SetIsolationMode>, isolated: bool, debt_ceiling: u64) -> Result<()> {
+        let mint = ctx.accounts.collateral_mint.key();
+        let asset = ctx.accounts.lending_pool.collateral_asset_mut(&mint)?;
+
+        asset.isolated = isolated;
+        asset.debt_ceiling = debt_ceiling;
+
+        msg!("Collateral asset {} isolated: {}, debt ceiling {}", mint, isolated, debt_ceiling);
+
+        Ok(())
+    }
+
+    /// Approves native SOL, the pool's only borrowable asset, for borrowing
+    /// against isolated collateral. This flag stands in for a per-asset
+    /// allowlist of approved stablecoins until the pool lends anything else.
+    pub fn set_isolated_borrowing(ctx: Context<SetIsolatedBorrowing>, enabled: bool) -> Result<()> {
+        ctx.accounts.lending_pool.isolated_borrowing_enabled = enabled;
+
+        msg!("Borrowing against isolated collateral enabled: {}", enabled);
+
+        Ok(())
+    }
+
+    pub fn set_emode_category(
+        ctx: Context<SetEModeCategory>,
+        id: u8,
+        ltv_bps: u16,
+        liquidation_threshold: u16,
+        liquidation_bonus: u16,
+    ) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+
+        // A position opened at the category LTV must not start out liquidatable
+        require!(
+            id != EMODE_NONE
+                && ltv_bps > 0
+                && liquidation_threshold > BASIS_POINTS
+                && (ltv_bps as u32 * liquidation_threshold as u32) < BASIS_POINTS as u32 * BASIS_POINTS as u32
+                && liquidation_bonus <= MAX_EMODE_LIQUIDATION_BONUS,
+            ArciLendError::InvalidEModeCategory
+        );
+
+        let category = EModeCategory { id, ltv_bps, liquidation_threshold, liquidation_bonus };
+
+        let count = lending_pool.emode_category_count as usize;
+        match lending_pool.emode_categories[..count].iter().position(|c| c.id == id) {
+            Some(index) => lending_pool.emode_categories[index] = category,
+            None => {
+                require!(count < MAX_EMODE_CATEGORIES, ArciLendError::InvalidEModeCategory);
+                lending_pool.emode_categories[count] = category;
+                lending_pool.emode_category_count += 1;
+            }
+        }
+
+        msg!("E-mode category {}: LTV {}bps, liquidation {}bps", id, ltv_bps, liquidation_threshold);
+
+        Ok(())
+    }
+
+    pub fn set_asset_emode_category(ctx: Context<SetAssetEModeCategory>, category: u8) -> Result<()> {
+        let mint = ctx.accounts.collateral_mint.key();
+
+        if category != EMODE_NONE {
+            ctx.accounts.lending_pool.emode_category(category)?;
+        }
+        ctx.accounts.lending_pool.collateral_asset_mut(&mint)?.emode_category = category;
+
+        msg!("Collateral asset {} e-mode category: {}", mint, category);
+
+        Ok(())
+    }
+
+    /// Opts into an e-mode category, or leaves e-mode with `EMODE_NONE`.
+    /// Remaining accounts carry the pricing accounts of every basket position.
+    pub fn enter_emode(ctx: Context<EnterEMode>, category: u8) -> Result<()> {
+        let lending_pool = &ctx.accounts.lending_pool;
+        let user_account = &mut ctx.accounts.user_account;
+        let clock = Clock::get()?;
+
+        // The category's terms only apply to its own assets, so there must be some
+        if category != EMODE_NONE {
+            lending_pool.emode_category(category)?;
+            require!(user_account.collateral_position_count > 0, ArciLendError::NoEModeCollateral);
+        }
+        user_account.emode_category = category;
+
+        // Every collateral asset has to belong to the chosen category, and
+        // outstanding commitments must still fit the new borrowing power
+        let basket = lending_pool.basket_value(user_account, &mut ctx.remaining_accounts.iter(), &clock)?;
+        let borrowing_power = lending_pool.borrowing_power(user_account, &basket, clock.unix_timestamp)?;
+        require!(
+            user_account.committed_credit() as u128 <= borrowing_power,
+            ArciLendError::ExceedsRiskAdjustedLTV
+        );
+
+        user_account.last_update = clock.unix_timestamp;
+
+        msg!("E-mode category set to {}", category);
+
+        Ok(())
+    }
+
+    pub fn deposit_basket_collateral<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositBasketCollateral<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let mint = ctx.accounts.collateral_mint.key();
+        let lending_pool = &ctx.accounts.lending_pool;
+
+        require!(amount > 0, ArciLendError::InsufficientBalance);
+        let asset = lending_pool.collateral_asset(&mint)?;
+        require!(
+            asset.total_supplied + amount <= asset.supply_cap,
+            ArciLendError::SupplyCapExceeded
+        );
+
+        // A transfer hook program can be changed after listing, so extensions
+        // are checked again on every deposit
+        lending_pool.validate_mint_extensions(&ctx.accounts.collateral_mint.to_account_info())?;
+
+        // Isolated collateral can't share a basket with any other asset
+        let mixes_isolation = ctx.accounts.user_account.collateral_positions().iter().any(|position| {
+            position.mint != mint
+                && (asset.isolated
+                    || lending_pool
+                        .collateral_asset(&position.mint)
+                        .is_ok_and(|other| other.isolated))
+        });
+        require!(!mixes_isolation, ArciLendError::IsolatedCollateralMix);
+        require!(
+            ctx.accounts.user_account.emode_category == EMODE_NONE
+                || asset.emode_category == ctx.accounts.user_account.emode_category,
+            ArciLendError::EModeCategoryMismatch
+        );
+
+        let vault_balance = ctx.accounts.collateral_vault.amount;
+
+        // Remaining accounts are forwarded for allowlisted transfer hooks
+        let cpi_context = CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::TransferChecked {
+                from: ctx.accounts.user_token_account.to_account_info(),
+                mint: ctx.accounts.collateral_mint.to_account_info(),
+                to: ctx.accounts.collateral_vault.to_account_info(),
+                authority: ctx.accounts.user.to_account_info(),
+            },
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        token_interface::transfer_checked(cpi_context, amount, ctx.accounts.collateral_mint.decimals)?;
+
+        // Transfer-fee mints deliver less than `amount`; only what arrived is collateral
+        ctx.accounts.collateral_vault.reload()?;
+        let received = ctx.accounts.collateral_vault.amount - vault_balance;
+        require!(received > 0, ArciLendError::InsufficientBalance);
+
+        ctx.accounts.lending_pool.collateral_asset_mut(&mint)?.total_supplied += received;
+
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.add_collateral_position(mint, received)?;
+        user_account.last_update = Clock::get()?.unix_timestamp;
+
+        msg!("Deposited {} of {} as collateral", received, mint);
+
+        Ok(())
+    }
+
+    pub fn withdraw_basket_collateral<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawBasketCollateral<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let mint = ctx.accounts.collateral_mint.key();
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let user_account = &mut ctx.accounts.user_account;
+        let clock = Clock::get()?;
+
+        user_account.remove_collateral_position(&mint, amount)?;
+        lending_pool.collateral_asset_mut(&mint)?.total_supplied -= amount;
+
+        // Whatever the user owes or has committed to others must still fit
+        // the remaining basket, priced through the remaining accounts
+        if user_account.committed_credit() > 0 {
+            let basket = lending_pool.basket_value(user_account, &mut ctx.remaining_accounts.iter(), &clock)?;
+            let borrowing_power = lending_pool.borrowing_power(user_account, &basket, clock.unix_timestamp)?;
+
+            require!(
+                user_account.committed_credit() as u128 <= borrowing_power,
+                ArciLendError::ExceedsRiskAdjustedLTV
+            );
+        }
+
+        user_account.last_update = clock.unix_timestamp;
+
+        // Pricing accounts come first; any transfer hook accounts follow them
+        let signer_seeds: &[&[&[u8]]] = &[&[LENDING_POOL_SEED, &[lending_pool.bump]]];
+        let cpi_context = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            token_interface::TransferChecked {
+                from: ctx.accounts.collateral_vault.to_account_info(),
+                mint: ctx.accounts.collateral_mint.to_account_info(),
+                to: ctx.accounts.user_token_account.to_account_info(),
+                authority: lending_pool.to_account_info(),
+            },
+            signer_seeds,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+        token_interface::transfer_checked(cpi_context, amount, ctx.accounts.collateral_mint.decimals)?;
+
+        msg!("Withdrew {} of {} collateral", amount, mint);
+
+        Ok(())
+    }
+
+    /// Takes custody of an active, delegated stake account by moving its stake
+    /// and withdraw authorities to the pool. It counts as SOL collateral at its
+    /// delegated lamports.
+    pub fn deposit_stake_collateral(ctx: Context<DepositStakeCollateral>) -> Result<()> {
+        let stake_account = &ctx.accounts.stake_account;
+        let clock = &ctx.accounts.clock;
+        let user = ctx.accounts.user.key();
+
+        let delegation = stake_account.delegation().ok_or(ArciLendError::InvalidStakeAccount)?;
+        let authorized = stake_account.authorized().ok_or(ArciLendError::InvalidStakeAccount)?;
+        let lockup = stake_account.lockup().ok_or(ArciLendError::InvalidStakeAccount)?;
+
+        require!(
+            delegation.activation_epoch < clock.epoch
+                && delegation.deactivation_epoch == u64::MAX
+                && delegation.stake > 0,
+            ArciLendError::InvalidStakeAccount
+        );
+        require!(
+            authorized.staker == user && authorized.withdrawer == user,
+            ArciLendError::InvalidStakeAccount
+        );
+        // The pool must be able to hand the account over again at any time
+        require!(!lockup.is_in_force(clock, None), ArciLendError::StakeAccountLocked);
+        ctx.accounts.lending_pool.check_supply_cap(delegation.stake)?;
+
+        authorize_stake(
+            ctx.accounts.stake_program.to_account_info(),
+            stake_account.to_account_info(),
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.lending_pool.to_account_info(),
+            clock.to_account_info(),
+            &[],
+        )?;
+
+        let stake_collateral = &mut ctx.accounts.stake_collateral;
+        stake_collateral.owner = user;
+        stake_collateral.stake_account = stake_account.key();
+        stake_collateral.lamports = delegation.stake;
+        stake_collateral.bump = ctx.bumps.stake_collateral;
+
+        ctx.accounts.lending_pool.total_stake_collateral += delegation.stake;
+
+        let user_account = &mut ctx.accounts.user_account;
+        user_account.stake_collateral = delegation.stake;
+        user_account.last_update = clock.unix_timestamp;
+
+        msg!("Stake account {} deposited as collateral", stake_collateral.stake_account);
+        msg!("Delegated lamports: {}", delegation.stake);
+
+        Ok(())
+    }
+
+    /// Returns a custodied stake account's authorities to its owner once
+    /// nothing is borrowed against or committed from the account.
+    pub fn withdraw_stake_collateral(ctx: Context<WithdrawStakeCollateral>) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let user_account = &mut ctx.accounts.user_account;
+
+        require!(user_account.committed_credit() == 0, ArciLendError::ActiveLoansExist);
+        // The account's principal doesn't include interest, so the loan itself must be settled
+        if user_account.loan_count > 0 {
+            let loan = ctx.accounts.loan.as_ref().ok_or(ArciLendError::ActiveLoansExist)?;
+            require!(loan.is_liquidated || loan.total_owed() == 0, ArciLendError::ActiveLoansExist);
+        }
+
+        authorize_stake(
+            ctx.accounts.stake_program.to_account_info(),
+            ctx.accounts.stake_account.to_account_info(),
+            lending_pool.to_account_info(),
+            ctx.accounts.user.to_account_info(),
+            ctx.accounts.clock.to_account_info(),
+            &[&[LENDING_POOL_SEED, &[lending_pool.bump]]],
+        )?;
+
+        lending_pool.total_stake_collateral -= user_account.stake_collateral;
+        user_account.stake_collateral = 0;
+        user_account.last_update = ctx.accounts.clock.unix_timestamp;
+
+        msg!("Stake account {} returned", ctx.accounts.stake_account.key());
+
+        Ok(())
+    }
+
+    pub fn set_credit_line_config(
+        ctx: Context<SetCreditLineConfig>,
+        min_ltv: u16,
+        max_line: u64,
+        exposure_limit: u64,
+        reserve_fee_bps: u16
+    ) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+
+        require!(
+            (MIN_LTV..=MAX_LTV).contains(&min_ltv) && reserve_fee_bps <= MAX_RESERVE_FEE_BPS,
+            ArciLendError::InvalidCreditLineConfig
+        );
+
+        lending_pool.credit_line_min_ltv = min_ltv;
+        lending_pool.credit_line_max = max_line;
+        lending_pool.credit_line_exposure_limit = exposure_limit;
+        lending_pool.credit_line_reserve_fee_bps = reserve_fee_bps;
+
+        msg!("Credit lines: min LTV {}, max {}, exposure limit {}", min_ltv, max_line, exposure_limit);
+
+        Ok(())
+    }
+
+    pub fn fund_loss_reserve(ctx: Context<FundLossReserve>, amount: u64) -> Result<()> {
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
+
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.funder.to_account_info(),
+                to: lending_pool_info,
+            },
+        );
+        system_program::transfer(cpi_context, amount)?;
+
+        ctx.accounts.lending_pool.credit_line_loss_reserve += amount;
+
+        msg!("Loss reserve funded with {} lamports", amount);
+
+        Ok(())
+    }
+
+    /// Opens an unsecured credit line for borrowers whose MPC-attested score
+    /// clears the pool's threshold
+    pub fn open_credit_line(ctx: Context<OpenCreditLine>) -> Result<()> {
+        let lending_pool = &ctx.accounts.lending_pool;
+        let user_account = &ctx.accounts.user_account;
+        let credit_line = &mut ctx.accounts.credit_line;
+        let clock = Clock::get()?;
+
+        require!(lending_pool.credit_line_max > 0, ArciLendError::CreditLinesDisabled);
+        require!(
+            user_account.effective_ltv(clock.unix_timestamp) >= lending_pool.credit_line_min_ltv,
+            ArciLendError::CreditScoreTooLow
+        );
+
+        credit_line.owner = user_account.owner;
+        credit_line.limit = lending_pool.credit_line_max;
+        credit_line.drawn = 0;
+        credit_line.accrued_interest = 0;
+        credit_line.interest_rate = 0;
+        credit_line.last_accrual = clock.unix_timestamp;
+        credit_line.due_at = 0;
+        credit_line.defaulted = false;
+        credit_line.bump = ctx.bumps.credit_line;
+
+        msg!("Credit line opened with limit {}", credit_line.limit);
+
+        Ok(())
+    }
+
+    pub fn draw_credit_line(ctx: Context<DrawCreditLine>, amount: u64) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let user_account = &mut ctx.accounts.user_account;
+        let credit_line = &mut ctx.accounts.credit_line;
+        let clock = Clock::get()?;
+
+        require!(amount > 0, ArciLendError::InsufficientBalance);
+        require!(!credit_line.defaulted, ArciLendError::CreditLineDefaulted);
+        require!(
+            credit_line.drawn == 0 || clock.unix_timestamp <= credit_line.due_at,
+            ArciLendError::CreditLinePastDue
+        );
+        require!(
+            lending_pool.total_borrowed + amount <= lending_pool.borrow_cap,
+            ArciLendError::BorrowCapExceeded
+        );
+        require!(
+            user_account.amount_borrowed + user_account.credit_line_drawn + amount <= lending_pool.max_user_borrow,
+            ArciLendError::UserBorrowLimitExceeded
+        );
+
+        // The score must still qualify at every draw, not just at opening
+        let risk_adjusted_ltv = user_account.effective_ltv(clock.unix_timestamp);
+        require!(
+            risk_adjusted_ltv >= lending_pool.credit_line_min_ltv,
+            ArciLendError::CreditScoreTooLow
+        );
+        require!(
+            credit_line.drawn + amount <= credit_line.limit.min(lending_pool.credit_line_max),
+            ArciLendError::ExceedsCreditLine
+        );
+        require!(
+            lending_pool.credit_line_exposure + amount <= lending_pool.credit_line_exposure_limit,
+            ArciLendError::CreditLineExposureLimit
+        );
+        require!(
+            amount <= lending_pool.total_deposits.saturating_sub(lending_pool.total_borrowed),
+            ArciLendError::InsufficientLiquidity
+        );
+
+        credit_line.accrue_interest(clock.unix_timestamp);
+
+        let tier = lending_pool.risk_tier(risk_adjusted_ltv);
+        let reserve_fee = (amount as u128 * lending_pool.credit_line_reserve_fee_bps as u128 / BASIS_POINTS as u128) as u64;
+        let disbursed = amount - reserve_fee;
+
+        // The term runs from the first draw; later draws don't extend it
+        if credit_line.drawn == 0 {
+            credit_line.due_at = clock.unix_timestamp + lending_pool.loan_term;
+        }
+
+        credit_line.drawn += amount;
+        credit_line.interest_rate = lending_pool.get_curent_interest_rate() + tier.premium_bps;
+
+        let utilization_bps = (credit_line.drawn as u128 * BASIS_POINTS as u128)
+            .checked_div(credit_line.limit as u128)
+            .unwrap_or(0)
+            .min(u16::MAX as u128) as u16;
+        user_account.max_utilization_bps = user_account.max_utilization_bps.max(utilization_bps);
+        user_account.credit_line_drawn += amount;
+        user_account.last_update = clock.unix_timestamp;
+
+        **lending_pool.to_account_info().try_borrow_mut_lamports()? -= disbursed;
+        **ctx.accounts.borrower.to_account_info().try_borrow_mut_lamports()? += disbursed;
+
+        lending_pool.credit_line_loss_reserve += reserve_fee;
+        lending_pool.credit_line_exposure += amount;
+        lending_pool.total_borrowed += amount;
+        lending_pool.calculate_utilization();
+
+        msg!("Drew {} lamports on credit line at {}bps", amount, credit_line.interest_rate);
+        msg!("Reserve fee: {}", reserve_fee);
+
+        Ok(())
+    }
+
+    pub fn repay_credit_line(ctx: Context<RepayCreditLine>, amount: u64) -> Result<()> {
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let user_account = &mut ctx.accounts.user_account;
+        let credit_line = &mut ctx.accounts.credit_line;
+        let clock = Clock::get()?;
+
+        require!(!credit_line.defaulted, ArciLendError::CreditLineDefaulted);
+
+        credit_line.accrue_interest(clock.unix_timestamp);
+
+        let repay_amount = amount.min(credit_line.total_owed());
+        require!(repay_amount > 0, ArciLendError::InsufficientBalance);
+
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: lending_pool_info,
+            },
+        );
+        system_program::transfer(cpi_context, repay_amount)?;
+
+        let principal_payment = credit_line.apply_repayment(repay_amount);
+
+        user_account.total_repaid_volume += repay_amount;
+        user_account.credit_line_drawn = user_account.credit_line_drawn.saturating_sub(principal_payment);
+        user_account.last_update = clock.unix_timestamp;
+        ctx.accounts.reputation.total_repaid_volume += repay_amount;
+        ctx.accounts.reputation.last_update = clock.unix_timestamp;
+
+        lending_pool.credit_line_exposure -= principal_payment;
+        lending_pool.total_borrowed = lending_pool.total_borrowed.saturating_sub(principal_payment);
+        lending_pool.calculate_utilization();
+
+        msg!("Repaid {} lamports on credit line", repay_amount);
+
+        Ok(())
+    }
+
+    /// Permissionless once a credit line is past due. The loss reserve absorbs
+    /// what it can; the rest is recorded as bad debt.
+    pub fn default_credit_line(ctx: Context<DefaultCreditLine>) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let user_account = &mut ctx.accounts.user_account;
+        let credit_line = &mut ctx.accounts.credit_line;
+        let clock = Clock::get()?;
+
+        require!(!credit_line.defaulted, ArciLendError::CreditLineDefaulted);
+        require!(
+            credit_line.drawn > 0 && clock.unix_timestamp > credit_line.due_at,
+            ArciLendError::CreditLineNotPastDue
+        );
+
+        credit_line.accrue_interest(clock.unix_timestamp);
+
+        let (loss, covered) = lending_pool.write_off_credit_line(credit_line);
+        lending_pool.calculate_utilization();
+
+        credit_line.defaulted = true;
+
+        user_account.defaults += 1;
+        user_account.credit_line_drawn = user_account.credit_line_drawn.saturating_sub(credit_line.drawn);
+        user_account.last_update = clock.unix_timestamp;
+        ctx.accounts.reputation.defaults += 1;
+        ctx.accounts.reputation.last_update = clock.unix_timestamp;
+
+        msg!("Credit line defaulted: loss {}, covered by reserve {}", loss, covered);
+
+        Ok(())
+    }
+
+    pub fn liquidate<'info>(ctx: Context<'_, '_, '_, 'info, Liquidate<'info>>) -> Result<()>{
+        let user_account = &mut ctx.accounts.user_account;
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let loan = &mut ctx.accounts.loan;
+        let clock = Clock::get()?;
+
+        require!(!loan.is_liquidated, ArciLendError::AlreadyLiquidated);
+
+        loan.accrue_interest(clock.unix_timestamp);
+
+        let collateral_price = lending_pool.collateral_price(&ctx.accounts.oracle_feed)?;
+        let total_dept = loan.total_owed();
+
+        // A delegated loan is backed by the delegator's collateral: it becomes
+        // liquidatable once the delegator's position is unhealthy, and is
+        // seized from the delegator up to the debt delegated to them. Pricing
+        // accounts for the delegator's basket are passed as remaining accounts.
+        if loan.delegation != Pubkey::default() {
+            let credit_delegation = ctx.accounts.credit_delegation.as_mut().ok_or(ArciLendError::InvalidDelegation)?;
+            let delegator_account = ctx.accounts.delegator_account.as_mut().ok_or(ArciLendError::InvalidDelegation)?;
+            require!(
+                credit_delegation.key() == loan.delegation
+                    && delegator_account.owner == credit_delegation.delegator,
+                ArciLendError::InvalidDelegation
+            );
+
+            let basket = lending_pool.basket_value(delegator_account, &mut ctx.remaining_accounts.iter(), &clock)?;
+            let (liquidation_threshold, liquidation_bonus, basket_liquidation_value) =
+                lending_pool.liquidation_terms(delegator_account, &basket)?;
+            require!(
+                delegator_account.is_liquidatable(collateral_price, liquidation_threshold, basket_liquidation_value),
+                ArciLendError::LoanNotLiquidatable
+            );
+
+            // The bonus comes out of the delegator's collateral along with the debt
+            let delegator_seized = liquidation_seizure(
+                total_dept,
+                liquidation_bonus,
+                collateral_price,
+                delegator_account.collateral_deposited,
+            );
+            let bonus = delegator_seized.saturating_sub(collateral_for_value(total_dept, collateral_price));
+
+            // The liquidator pays off the debt and receives the seized collateral
+            let cpi_context = CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.liquidator.to_account_info(),
+                    to: lending_pool.to_account_info(),
+                },
+            );
+            system_program::transfer(cpi_context, total_dept)?;
+
+            **lending_pool.to_account_info().try_borrow_mut_lamports()? -= delegator_seized;
+            **ctx.accounts.liquidator.to_account_info().try_borrow_mut_lamports()? += delegator_seized;
+
+            credit_delegation.used = credit_delegation.used.saturating_sub(loan.borrowed_amount);
+            delegator_account.delegated_debt = delegator_account.delegated_debt.saturating_sub(loan.borrowed_amount);
+            delegator_account.collateral_deposited -= delegator_seized;
+            delegator_account.last_update = clock.unix_timestamp;
+
+            loan.is_liquidated = true;
+            user_account.defaults += 1;
+            user_account.liquidations += 1;
+            ctx.accounts.reputation.record_liquidation(clock.unix_timestamp);
+
+            lending_pool.release_isolated_debt(loan, loan.borrowed_amount);
+            lending_pool.total_borrowed -= loan.borrowed_amount;
+            lending_pool.total_deposits -= delegator_seized;
+            lending_pool.calculate_utilization();
+
+            msg!("Delegated loan liquidated!");
+            msg!("Debt: {}, seized from delegator {}: {}, Bonus: {}", total_dept, credit_delegation.delegator, delegator_seized, bonus);
+
+            return Ok(());
+        }
+
+        let mut remaining_accounts = ctx.remaining_accounts.iter();
+        let basket = lending_pool.basket_value(user_account, &mut remaining_accounts, &clock)?;
+        let (liquidation_threshold, liquidation_bonus, basket_liquidation_value) =
+            lending_pool.liquidation_terms(user_account, &basket)?;
+
+        require!(
+            user_account.is_liquidatable(
+                collateral_price,
+                liquidation_threshold,
+                loan.guaranteed_amount + basket_liquidation_value
+            ),
+            ArciLendError::LoanNotLiquidatable
+        );
+
+        // Each source of collateral covers the debt the ones before it couldn't,
+        // and the liquidation bonus comes out of the collateral it gives up
+        let borrower_seized = liquidation_seizure(
+            total_dept,
+            liquidation_bonus,
+            collateral_price,
+            user_account.seizable_collateral(loan),
+        );
+        let mut uncovered =
+            total_dept.saturating_sub(debt_covered(collateral_value(borrower_seized, collateral_price), liquidation_bonus));
+
+        // A custodied stake account is only taken when the SOL collateral
+        // doesn't cover the debt. It can't be split, so it goes to the
+        // liquidator whole by handing over its authorities (the record's rent
+        // goes along with it) and the liquidator pays back whatever it is
+        // worth beyond the debt it covers plus the bonus, which is credited
+        // to the borrower's SOL collateral.
+        let mut stake_seized = 0;
+        let mut stake_surplus = 0;
+        if uncovered > 0 && user_account.stake_collateral > 0 {
+            let stake_collateral = ctx.accounts.stake_collateral.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            let stake_account = ctx.accounts.stake_account.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            let clock_sysvar = ctx.accounts.clock.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            let stake_program = ctx.accounts.stake_program.as_ref().ok_or(ArciLendError::InvalidStakeAccount)?;
+            require_keys_eq!(stake_account.key(), stake_collateral.stake_account, ArciLendError::InvalidStakeAccount);
+
+            authorize_stake(
+                stake_program.to_account_info(),
+                stake_account.to_account_info(),
+                lending_pool.to_account_info(),
+                ctx.accounts.liquidator.to_account_info(),
+                clock_sysvar.to_account_info(),
+                &[&[LENDING_POOL_SEED, &[lending_pool.bump]]],
+            )?;
+            stake_collateral.close(ctx.accounts.liquidator.to_account_info())?;
+
+            stake_seized = user_account.stake_collateral;
+            stake_surplus = stake_surplus_value(stake_seized, uncovered, liquidation_bonus, collateral_price);
+            if stake_surplus > 0 {
+                let cpi_context = CpiContext::new(
+                    ctx.accounts.system_program.to_account_info(),
+                    system_program::Transfer {
+                        from: ctx.accounts.liquidator.to_account_info(),
+                        to: lending_pool.to_account_info(),
+                    },
+                );
+                system_program::transfer(cpi_context, stake_surplus)?;
+
+                user_account.collateral_deposited += stake_surplus;
+                lending_pool.total_deposits += stake_surplus;
+            }
+
+            let value = collateral_value(stake_seized - stake_surplus, collateral_price);
+            uncovered = uncovered.saturating_sub(debt_covered(value, liquidation_bonus));
+            lending_pool.total_stake_collateral -= stake_seized;
+            user_account.stake_collateral = 0;
+        }
+
+        // Basket positions cover the debt SOL and stake couldn't. Per position, the mint, its vault, the liquidator's
+        // token account and the token program follow the pricing accounts,
+        // then any transfer hook accounts.
+        let mut basket_seized = 0;
+        let positions = user_account.collateral_positions().to_vec();
+        let seizure_accounts = remaining_accounts.as_slice();
+        let hook_accounts = seizure_accounts.get(positions.len() * 4..).unwrap_or_default();
+
+        for (index, position) in positions.iter().enumerate() {
+            let price = basket.prices[index];
+            let amount = liquidation_seizure(uncovered, liquidation_bonus, price, position.amount);
+            if amount == 0 {
+                break;
+            }
+
+            let accounts = seizure_accounts
+                .get(index * 4..index * 4 + 4)
+                .ok_or(ArciLendError::InvalidCollateralAsset)?;
+            let asset = lending_pool.collateral_asset(&position.mint)?;
+            seize_basket_position(lending_pool, asset, accounts, hook_accounts, amount)?;
+            user_account.remove_collateral_position(&position.mint, amount)?;
+            lending_pool.collateral_asset_mut(&position.mint)?.total_supplied -= amount;
+
+            let value = collateral_value(amount, price);
+            basket_seized += value;
+            uncovered = uncovered.saturating_sub(debt_covered(value, liquidation_bonus));
+        }
+
+        // The guarantor only covers what the borrower's own collateral can't
+        let mut guarantor_seized = 0;
+        if loan.guarantor != Pubkey::default() {
+            let guarantor_account = ctx
+                .accounts
+                .guarantor_account
+                .as_mut()
+                .ok_or(ArciLendError::InvalidGuarantee)?;
+            require!(guarantor_account.owner == loan.guarantor, ArciLendError::InvalidGuarantee);
+
+            guarantor_seized = liquidation_seizure(
+                uncovered.min(loan.guaranteed_amount),
+                liquidation_bonus,
+                collateral_price,
+                guarantor_account.collateral_deposited,
+            );
+
+            guarantor_account.collateral_deposited -= guarantor_seized;
+            guarantor_account.amount_guaranteed = guarantor_account.amount_guaranteed.saturating_sub(loan.guaranteed_amount);
+            guarantor_account.last_update = clock.unix_timestamp;
+            loan.guaranteed_amount = 0;
+        }
+
+        let collateral_to_seize = borrower_seized + guarantor_seized;
+
+        // The liquidator pays off the debt and receives the seized collateral
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.liquidator.to_account_info(),
+                to: lending_pool.to_account_info(),
+            },
+        );
+        system_program::transfer(cpi_context, total_dept)?;
+
+        **lending_pool.to_account_info().try_borrow_mut_lamports()? -= collateral_to_seize;
+        **ctx.accounts.liquidator.to_account_info().try_borrow_mut_lamports()? += collateral_to_seize;
+
+        loan.is_liquidated = true;
+        loan.collateral_amount -= borrower_seized;
+        user_account.amount_borrowed -= loan.borrowed_amount;
+        user_account.collateral_deposited -= borrower_seized;
+        user_account.defaults += 1;
+        user_account.liquidations += 1;
+        ctx.accounts.reputation.record_liquidation(clock.unix_timestamp);
+
+        lending_pool.release_isolated_debt(loan, loan.borrowed_amount);
+        lending_pool.total_borrowed -= loan.borrowed_amount;
+        lending_pool.total_deposits -= collateral_to_seize;
+        lending_pool.calculate_utilization();
+
+        msg!("Liquidation successful!");
+        msg!("Debt: {}, seized: {}", total_dept, collateral_to_seize);
+        if guarantor_seized > 0 {
+            msg!("Seized {} from guarantor {}", guarantor_seized, loan.guarantor);
+        }
+        if stake_seized > 0 {
+            msg!("Stake account worth {} handed to liquidator, {} returned to borrower", stake_seized, stake_surplus);
+        }
+        if basket_seized > 0 {
+            msg!("Seized basket collateral worth {}", basket_seized);
+        }
+
+        Ok(())
+    }
+
+    pub fn set_risk_tiers(ctx: Context<SetRiskTiers>, tiers: Vec<RiskTier>) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+
+        require!(
+            !tiers.is_empty() && tiers.len() <= MAX_RISK_TIERS,
+            ArciLendError::InvalidRiskTiers
+        );
+        // Bands must start at MIN_LTV so every valid LTV falls into one
+        require!(tiers[0].min_ltv == MIN_LTV, ArciLendError::InvalidRiskTiers);
+        for (i, tier) in tiers.iter().enumerate() {
+            require!(
+                tier.min_ltv <= MAX_LTV
+                    && tier.premium_bps <= BASIS_POINTS
+                    && tier.max_loan > 0
+                    && (i == 0 || tier.min_ltv > tiers[i - 1].min_ltv),
+                ArciLendError::InvalidRiskTiers
+            );
+        }
+
+        lending_pool.risk_tiers = [RiskTier::default(); MAX_RISK_TIERS];
+        lending_pool.risk_tiers[..tiers.len()].copy_from_slice(&tiers);
+        lending_pool.risk_tier_count = tiers.len() as u8;
+
+        msg!("Risk tiers updated: {} bands", tiers.len());
+
+        Ok(())
+    }
+
+    pub fn set_pool_caps(
+        ctx: Context<SetPoolCaps>,
+        supply_cap: u64,
+        borrow_cap: u64,
+        max_user_borrow: u64,
+    ) -> Result<()> {
+        require!(
+            borrow_cap <= supply_cap && max_user_borrow <= borrow_cap,
+            ArciLendError::InvalidPoolCaps
+        );
+
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        lending_pool.supply_cap = supply_cap;
+        lending_pool.borrow_cap = borrow_cap;
+        lending_pool.max_user_borrow = max_user_borrow;
+
+        msg!("Supply cap: {}, borrow cap: {}", supply_cap, borrow_cap);
+        msg!("Max borrow per user: {}", max_user_borrow);
+
+        Ok(())
+    }
+
+    /// Sets the term new loans are due within; repayments after it count as late
+    pub fn set_loan_term(ctx: Context<SetLoanTerm>, loan_term: i64) -> Result<()> {
+        require!(loan_term > 0, ArciLendError::InvalidLoanTerm);
+
+        ctx.accounts.lending_pool.loan_term = loan_term;
+
+        msg!("Loan term set to {}s", loan_term);
+
+        Ok(())
+    }
+
+    pub fn set_flash_fee(ctx: Context<SetFlashFee>, flash_fee_bps: u16) -> Result<()> {
+        require!(flash_fee_bps <= MAX_FLASH_FEE_BPS, ArciLendError::InvalidFlashFee);
+
+        ctx.accounts.lending_pool.flash_fee_bps = flash_fee_bps;
+
+        msg!("Flash fee set to {}bps", flash_fee_bps);
+
+        Ok(())
+    }
+
+    pub fn flash_borrow(ctx: Context<FlashBorrow>, amount: u64) -> Result<()> {
+        let lending_pool = &mut ctx.accounts.lending_pool;
+        let instructions = &ctx.accounts.instructions;
+
+        require!(amount > 0, ArciLendError::InsufficientBalance);
+        require!(lending_pool.flash_loan_amount == 0, ArciLendError::FlashLoanActive);
+        require!(
+            amount <= lending_pool.total_deposits.saturating_sub(lending_pool.total_borrowed),
+            ArciLendError::InsufficientLiquidity
+        );
+
+        // Introspection only sees top-level instructions, so a CPI caller could
+        // otherwise borrow without ever repaying
+        let current_ix = get_instruction_relative(0, instructions)?;
+        require_keys_eq!(current_ix.program_id, crate::ID, ArciLendError::FlashLoanCpiNotAllowed);
+
+        // The first flash instruction after this one must be the matching repay
+        let current_index = load_current_index_checked(instructions)? as usize;
+        let mut index = current_index + 1;
+        loop {
+            let ix = load_instruction_at_checked(index, instructions)
+                .map_err(|_| ArciLendError::FlashRepayMissing)?;
+
+            if ix.program_id == crate::ID && ix.data.len() >= 8 {
+                let discriminator = &ix.data[..8];
+
+                require!(
+                    discriminator != instruction::FlashBorrow::DISCRIMINATOR,
+                    ArciLendError::FlashLoanActive
+                );
+
+                if discriminator == instruction::FlashRepay::DISCRIMINATOR {
+                    let repay_amount = ix.data[8..]
+                        .try_into()
+                        .map(u64::from_le_bytes)
+                        .map_err(|_| ArciLendError::FlashLoanMismatch)?;
+
+                    require!(repay_amount == amount, ArciLendError::FlashLoanMismatch);
+                    break;
+                }
+            }
+
+            index += 1;
+        }
+
+        lending_pool.flash_loan_amount = amount;
+
+        **lending_pool.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.borrower.to_account_info().try_borrow_mut_lamports()? += amount;
+
+        msg!("Flash borrowed {} lamports", amount);
+
+        Ok(())
+    }
+
+    pub fn flash_repay(ctx: Context<FlashRepay>, amount: u64) -> Result<()> {
+        let lending_pool_info = ctx.accounts.lending_pool.to_account_info();
+        let lending_pool = &mut ctx.accounts.lending_pool;
+
+        require!(lending_pool.flash_loan_amount > 0, ArciLendError::NoActiveFlashLoan);
+        require!(amount == lending_pool.flash_loan_amount, ArciLendError::FlashLoanMismatch);
+
+        let fee = lending_pool.flash_fee(amount);
+
+        let cpi_context = CpiContext::new(
+            ctx.accounts.system_program.to_account_info(),
+            system_program::Transfer {
+                from: ctx.accounts.borrower.to_account_info(),
+                to: lending_pool_info,
+            },
+        );
+        system_program::transfer(cpi_context, amount + fee)?;
+
+        lending_pool.flash_loan_amount = 0;
+        lending_pool.total_fees += fee;
+
+        msg!("Flash repaid {} lamports, fee: {}", amount, fee);
+
+        Ok(())
+    }
+
+    pub fn accure_interest(ctx: Context<AccrueInterest>) -> Result<()>{
+        let loan = &mut ctx.accounts.loan;
+        let clock = Clock::get()?;
+
+        loan.accrue_interest(clock.unix_timestamp);
+
+        msg!("Interest accrued: {} lamports", loan.accrued_interest);
+
+        Ok(())
+    }
+}
+
+/// ---- Accounts ----
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + LendingPool::INIT_SPACE,
+        seeds = [LENDING_POOL_SEED],
+        bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+    /// CHECK: Initial Arcium MPC node key; the full node set and threshold are managed with `set_mpc_nodes`
+    pub arcium_mpc_pubkey: AccountInfo<'info>,
+    /// CHECK: This is the oracle feed address (Pyth/Switchboard) for price data
+    pub oracle_feed: AccountInfo<'info>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct DepositCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserAccount::INIT_SPACE,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Reputation::INIT_SPACE,
+        seeds = [REPUTATION_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    /// CHECK: Another pool's `Reputation` for `user`, validated against the
+    /// pool's reputation sources. Only read when the user account is new.
+    pub imported_reputation: Option<UncheckedAccount<'info>>,
+
+    pub system_program: Program<'info, System>
+
+}
+
+#[derive(Accounts)]
+pub struct ConfigureMxe<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    /// CHECK: Arcium MXE program that runs the confidential credit model
+    #[account(executable)]
+    pub mxe_program: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMpcNodes<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetCreditScoreConfig<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct ScheduleMpcKeyRotation<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct ActivateMpcKeyRotation<'info> {
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct RequestCreditScore<'info> {
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + CreditScoreComputation::INIT_SPACE,
+        seeds = [CREDIT_COMPUTATION_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub credit_computation: Account<'info, CreditScoreComputation>,
+
+    /// CHECK: Computation account created and owned by the MXE program
+    #[account(mut)]
+    pub mxe_computation: AccountInfo<'info>,
+
+    /// CHECK: Must be the MXE program configured on the pool
+    #[account(address = lending_pool.mxe_program @ ArciLendError::MxeNotConfigured)]
+    pub mxe_program: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct UpdateCreditScore<'info> {
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user_account.owner.as_ref()],
+        bump = user_account.bump,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [CREDIT_COMPUTATION_SEED, user_account.owner.as_ref()],
+        bump = credit_computation.bump,
+    )]
+    pub credit_computation: Account<'info, CreditScoreComputation>,
+
+    /// MXE signer PDA, only signable by the configured MXE program
+    pub mpc_authority: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, used to read the MPC node attestations
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(recipient_key: [u8; 32])]
+pub struct RequestScoreDisclosure<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + ScoreDisclosure::INIT_SPACE,
+        seeds = [SCORE_DISCLOSURE_SEED, user.key().as_ref(), recipient_key.as_ref()],
+        bump
+    )]
+    pub score_disclosure: Account<'info, ScoreDisclosure>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct FulfillScoreDisclosure<'info> {
+    #[account(
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [SCORE_DISCLOSURE_SEED, score_disclosure.user.as_ref(), score_disclosure.recipient_key.as_ref()],
+        bump = score_disclosure.bump
+    )]
+    pub score_disclosure: Account<'info, ScoreDisclosure>,
+
+    /// CHECK: Instructions sysvar, used to read the MPC node attestations
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyCreditThreshold<'info> {
+    #[account(
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        seeds = [USER_ACCOUNT_SEED, user_account.owner.as_ref()],
+        bump = user_account.bump,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    /// CHECK: Instructions sysvar, used to read the MPC node attestations
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimCreditScoreFees<'info> {
+    #[account(mut)]
+    pub mpc_operator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = mpc_operator @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct DelegateCredit<'info> {
+    #[account(mut)]
+    pub delegator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, delegator.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == delegator.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    /// CHECK: Any address may receive delegated credit
+    pub delegatee: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = delegator,
+        space = 8 + CreditDelegation::INIT_SPACE,
+        seeds = [DELEGATION_SEED, delegator.key().as_ref(), delegatee.key().as_ref()],
+        bump
+    )]
+    pub credit_delegation: Account<'info, CreditDelegation>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct RevokeCreditDelegation<'info> {
+    #[account(mut)]
+    pub delegator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, delegator.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == delegator.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        close = delegator,
+        has_one = delegator @ ArciLendError::Unauthorized,
+        seeds = [DELEGATION_SEED, delegator.key().as_ref(), credit_delegation.delegatee.as_ref()],
+        bump = credit_delegation.bump
+    )]
+    pub credit_delegation: Account<'info, CreditDelegation>,
+}
+
+#[derive(Accounts)]
+pub struct Borrow<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, borrower.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == borrower.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init,
+        payer = borrower,
+        space = 8 + Loan::INIT_SPACE,
+        seeds = [LOAN_SEED, borrower.key().as_ref()],
+        bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        mut,
+        constraint = credit_delegation.delegatee == borrower.key() @ ArciLendError::InvalidDelegation
+    )]
+    pub credit_delegation: Option<Account<'info, CreditDelegation>>,
+
+    #[account(mut)]
+    pub delegator_account: Option<Account<'info, UserAccount>>,
+
+    /// Co-signer pledging collateral for `guaranteed_amount`
+    pub guarantor: Option<Signer<'info>>,
+
+    #[account(mut)]
+    pub guarantor_account: Option<Account<'info, UserAccount>>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct Repay<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, loan.borrower.as_ref()],
+        bump = user_account.bump,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, loan.borrower.key().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        mut,
+        seeds = [REPUTATION_SEED, loan.borrower.as_ref()],
+        bump = reputation.bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    #[account(
+        mut,
+        constraint = credit_delegation.delegatee == loan.borrower @ ArciLendError::InvalidDelegation
+    )]
+    pub credit_delegation: Option<Account<'info, CreditDelegation>>,
+
+    #[account(mut)]
+    pub delegator_account: Option<Account<'info, UserAccount>>,
+
+    #[account(mut)]
+    pub guarantor_account: Option<Account<'info, UserAccount>>,
+
+    /// Required when this repayment clears the last of the borrower's debt
+    /// while a stake account is in custody
+    #[account(
+        mut,
+        seeds = [STAKE_COLLATERAL_SEED, loan.borrower.as_ref()],
+        bump = stake_collateral.bump
+    )]
+    pub stake_collateral: Option<Account<'info, StakeCollateral>>,
+
+    #[account(mut)]
+    pub stake_account: Option<Account<'info, StakeAccount>>,
+
+    pub clock: Option<Sysvar<'info, Clock>>,
+
+    pub stake_program: Option<Program<'info, Stake>>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct RepayFor<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, loan.borrower.as_ref()],
+        bump = user_account.bump,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, loan.borrower.as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        mut,
+        seeds = [REPUTATION_SEED, loan.borrower.as_ref()],
+        bump = reputation.bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    #[account(
+        mut,
+        constraint = credit_delegation.delegatee == loan.borrower @ ArciLendError::InvalidDelegation
+    )]
+    pub credit_delegation: Option<Account<'info, CreditDelegation>>,
+
+    #[account(mut)]
+    pub delegator_account: Option<Account<'info, UserAccount>>,
+
+    #[account(mut)]
+    pub guarantor_account: Option<Account<'info, UserAccount>>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct AddLoanCollateral<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, borrower.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == borrower.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, borrower.key().as_ref()],
+        bump = loan.bump,
+        constraint = loan.user_account == user_account.key()
+    )]
+    pub loan: Account<'info, Loan>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct Deleverage<'info> {
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, borrower.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == borrower.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, borrower.key().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        mut,
+        seeds = [REPUTATION_SEED, loan.borrower.as_ref()],
+        bump = reputation.bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    /// CHECK: Validated against `lending_pool.oracle_feed` when pricing collateral
+    pub oracle_feed: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub guarantor_account: Option<Account<'info, UserAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    /// Required only while the user has debt outstanding
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, user.key().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Option<Account<'info, Loan>>,
+
+    /// CHECK: Validated against `lending_pool.oracle_feed` when pricing collateral
+    pub oracle_feed: AccountInfo<'info>,
+
+    pub system_program: Program<'info, System>
+
+}
+
+#[derive(Accounts)]
+pub struct SetCollateralAsset<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct SetTransferHookAllowlist<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetReputationSources<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetAssetStakePool<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct SetAssetSupplyCap<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct SetIsolationMode<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct SetIsolatedBorrowing<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetEModeCategory<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetAssetEModeCategory<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct EnterEMode<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+}
+
+#[derive(Accounts)]
+pub struct DepositBasketCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = collateral_mint,
+        token::authority = user,
+        token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [COLLATERAL_VAULT_SEED, collateral_mint.key().as_ref()],
+        bump,
+        token::mint = collateral_mint,
+        token::authority = lending_pool,
+        token::token_program = token_program
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct WithdrawBasketCollateral<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    pub collateral_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = collateral_mint,
+        token::authority = user,
+        token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [COLLATERAL_VAULT_SEED, collateral_mint.key().as_ref()],
+        bump,
+        token::token_program = token_program
+    )]
+    pub collateral_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct DepositStakeCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(mut)]
+    pub stake_account: Account<'info, StakeAccount>,
+
+    #[account(
+        init,
+        payer = user,
+        space = 8 + StakeCollateral::INIT_SPACE,
+        seeds = [STAKE_COLLATERAL_SEED, user.key().as_ref()],
+        bump
+    )]
+    pub stake_collateral: Account<'info, StakeCollateral>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_program: Program<'info, Stake>,
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct WithdrawStakeCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, user.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == user.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        address = stake_collateral.stake_account @ ArciLendError::InvalidStakeAccount
+    )]
+    pub stake_account: Account<'info, StakeAccount>,
+
+    #[account(
+        mut,
+        close = user,
+        seeds = [STAKE_COLLATERAL_SEED, user.key().as_ref()],
+        bump = stake_collateral.bump
+    )]
+    pub stake_collateral: Account<'info, StakeCollateral>,
+
+    /// Required once the user has borrowed
+    #[account(
+        seeds = [LOAN_SEED, user.key().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Option<Account<'info, Loan>>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub stake_program: Program<'info, Stake>,
+}
+
+#[derive(Accounts)]
+pub struct Liquidate<'info> {
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, loan.borrower.as_ref()],
+        bump = user_account.bump,
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, loan.borrower.as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    #[account(
+        mut,
+        seeds = [REPUTATION_SEED, loan.borrower.as_ref()],
+        bump = reputation.bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    /// CHECK: Validated against `lending_pool.oracle_feed` when pricing collateral
+    pub oracle_feed: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(mut)]
+    pub guarantor_account: Option<Account<'info, UserAccount>>,
+
+    /// Required only for delegated loans
+    #[account(
+        mut,
+        constraint = credit_delegation.delegatee == loan.borrower @ ArciLendError::InvalidDelegation
+    )]
+    pub credit_delegation: Option<Account<'info, CreditDelegation>>,
+
+    #[account(mut)]
+    pub delegator_account: Option<Account<'info, UserAccount>>,
+
+    /// Required only while the borrower has a stake account in custody
+    #[account(
+        mut,
+        seeds = [STAKE_COLLATERAL_SEED, loan.borrower.as_ref()],
+        bump = stake_collateral.bump
+    )]
+    pub stake_collateral: Option<Account<'info, StakeCollateral>>,
+
+    #[account(mut)]
+    pub stake_account: Option<Account<'info, StakeAccount>>,
+
+    pub clock: Option<Sysvar<'info, Clock>>,
+
+    pub stake_program: Option<Program<'info, Stake>>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct SetCreditLineConfig<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct FundLossReserve<'info> {
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct OpenCreditLine<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        seeds = [USER_ACCOUNT_SEED, borrower.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == borrower.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        init,
+        payer = borrower,
+        space = 8 + CreditLine::INIT_SPACE,
+        seeds = [CREDIT_LINE_SEED, borrower.key().as_ref()],
+        bump
+    )]
+    pub credit_line: Account<'info, CreditLine>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct DrawCreditLine<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, borrower.key().as_ref()],
+        bump = user_account.bump,
+        constraint = user_account.owner == borrower.key()
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [CREDIT_LINE_SEED, borrower.key().as_ref()],
+        bump = credit_line.bump
+    )]
+    pub credit_line: Account<'info, CreditLine>,
+}
+
+#[derive(Accounts)]
+pub struct RepayCreditLine<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, credit_line.owner.as_ref()],
+        bump = user_account.bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [REPUTATION_SEED, credit_line.owner.as_ref()],
+        bump = reputation.bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    #[account(
+        mut,
+        seeds = [CREDIT_LINE_SEED, credit_line.owner.as_ref()],
+        bump = credit_line.bump
+    )]
+    pub credit_line: Account<'info, CreditLine>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct DefaultCreditLine<'info> {
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    #[account(
+        mut,
+        seeds = [USER_ACCOUNT_SEED, credit_line.owner.as_ref()],
+        bump = user_account.bump
+    )]
+    pub user_account: Account<'info, UserAccount>,
+
+    #[account(
+        mut,
+        seeds = [REPUTATION_SEED, credit_line.owner.as_ref()],
+        bump = reputation.bump
+    )]
+    pub reputation: Account<'info, Reputation>,
+
+    #[account(
+        mut,
+        seeds = [CREDIT_LINE_SEED, credit_line.owner.as_ref()],
+        bump = credit_line.bump
+    )]
+    pub credit_line: Account<'info, CreditLine>,
+}
+
+#[derive(Accounts)]
+pub struct SetRiskTiers<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolCaps<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetLoanTerm<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct SetFlashFee<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump,
+        has_one = authority @ ArciLendError::Unauthorized
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+}
+
+#[derive(Accounts)]
+pub struct FlashBorrow<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    /// CHECK: Instructions sysvar, used to find the matching flash repay
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    #[account(mut)]
+    pub borrower: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [LENDING_POOL_SEED],
+        bump = lending_pool.bump
+    )]
+    pub lending_pool: Account<'info, LendingPool>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct AccrueInterest<'info> {
+    #[account(
+        mut,
+        seeds = [LOAN_SEED, loan.borrower.as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>
+}
+
+/// ---- Event ----
+#[event]
+pub struct CreditScoreRequested {
+    pub user: Pubkey,
+    pub computation_offset: u64,
+    pub request_nonce: u64,
+    pub deadline: i64,
+    pub collateral_deposited: u64,
+    pub amount_borrowed: u64,
+    pub successful_repayments: u16,
+    pub defaults: u16,
+    pub total_repaid_volume: u64,
+    pub average_loan_duration: i64,
+    pub late_payments: u16,
+    pub max_utilization_bps: u16,
+    pub account_age: i64,
+    pub liquidations: u16,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct MpcKeyRotationScheduled {
+    pub old_key: Pubkey,
+    pub new_key: Pubkey,
+    pub activates_at: i64,
+    pub overlap_ends_at: i64,
+}
+
+#[event]
+pub struct MpcKeyRotationActivated {
+    pub old_key: Pubkey,
+    pub new_key: Pubkey,
+    pub overlap_ends_at: i64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ScoreDisclosureRequested {
+    pub user: Pubkey,
+    pub recipient_key: [u8; 32],
+    pub source_score: [u8; 32],
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ScoreDisclosed {
+    pub user: Pubkey,
+    pub recipient_key: [u8; 32],
+    pub ciphertext: [u8; 32],
+    pub nonce: u128,
+}
+
+/// ---- MXE Interface ----
+// Inputs to the confidential credit model, queued with the MXE. Results are
+// encrypted to `encryption_pubkey` and delivered through `update_credit_score`.
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct CreditScoreInputs {
+    pub user: Pubkey,
+    pub request_nonce: u64,
+    pub history: CreditHistory,
+    pub encryption_pubkey: [u8; 32],
+    pub nonce: u128,
+}
+
+/// Position and loan history aggregates the credit model scores
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct CreditHistory {
+    pub collateral_deposited: u64,
+    pub amount_borrowed: u64,
+    pub successful_repayments: u16,
+    pub defaults: u16,
+    pub total_repaid_volume: u64,
+    pub average_loan_duration: i64,
+    pub late_payments: u16,
+    pub max_utilization_bps: u16,
+    pub account_age: i64,
+    pub liquidations: u16,
+}
+
+/// ---- Lending Pool Struct ----
+
+#[account]
+#[derive(InitSpace)]
+pub struct LendingPool{
+    pub authority: Pubkey,
+    pub total_deposits: u64,
+    pub total_borrowed: u64,
+    pub interest_rate: u16,
+    pub collateral_ratio: u16,
+    pub liquidation_threshold: u16,
+    pub mpc_nodes: [Pubkey; MAX_MPC_NODES],
+    pub mpc_node_count: u8,
+    pub mpc_threshold: u8,
+    pub mpc_key_rotation: MpcKeyRotation,
+    pub oracle_feed: Pubkey,
+    pub bump: u8,
+    pub utilization_rate: u16,
+    pub total_fees: u64,
+    pub flash_fee_bps: u16,
+    pub flash_loan_amount: u64,
+    pub mxe_program: Pubkey,
+    pub mxe_signer: Pubkey,
+    pub mpc_operator: Pubkey,
+    pub credit_score_fee: u64,
+    pub credit_score_cooldown: i64,
+    pub credit_score_fees_escrowed: u64,
+    pub credit_score_fees_claimable: u64,
+    pub risk_tiers: [RiskTier; MAX_RISK_TIERS],
+    pub risk_tier_count: u8,
+    pub credit_line_min_ltv: u16,
+    pub credit_line_max: u64,
+    pub credit_line_exposure_limit: u64,
+    pub credit_line_exposure: u64,
+    pub credit_line_reserve_fee_bps: u16,
+    pub credit_line_loss_reserve: u64,
+    pub bad_debt: u64,
+    pub collateral_assets: [CollateralAsset; MAX_COLLATERAL_ASSETS],
+    pub collateral_asset_count: u8,
+    pub supply_cap: u64,
+    pub borrow_cap: u64,
+    pub max_user_borrow: u64,
+    // Single-asset form of the isolated borrow allowlist: debt is always native SOL
+    pub isolated_borrowing_enabled: bool,
+    pub emode_categories: [EModeCategory; MAX_EMODE_CATEGORIES],
+    pub emode_category_count: u8,
+    pub transfer_hook_allowlist: [Pubkey; MAX_TRANSFER_HOOK_PROGRAMS],
+    pub transfer_hook_count: u8,
+    pub loan_term: i64,
+    pub total_stake_collateral: u64,
+    pub reputation_sources: [Pubkey; MAX_REPUTATION_SOURCES],
+    pub reputation_source_count: u8,
 }
 
-#[derive(Accounts)]
-pub struct AccrueInterest<'info> {
-    #[account(
-        mut,
-        seeds = [LOAN_SEED, loan.borrower.as_ref()],
-        bump = loan.bump
-    )]
-    pub loan: Account<'info, Loan>
+/// Replacement of one MPC node key; both keys are accepted between
+/// `activates_at` and `overlap_ends_at`
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct MpcKeyRotation {
+    pub old_key: Pubkey,
+    pub new_key: Pubkey,
+    pub activates_at: i64,
+    pub overlap_ends_at: i64,
+    pub activated: bool,
+}
+
+/// SPL token accepted as basket collateral, priced through `oracle_feed` or,
+/// for liquid staking tokens, through `stake_pool`.
+/// `ltv_bps` weights its value for borrowing power and `liquidation_threshold_bps`
+/// for liquidation checks. Debt taken against an `isolated` asset is tracked in
+/// `isolated_debt` and capped at `debt_ceiling`. Deposits are tracked in token
+/// units in `total_supplied` and capped at `supply_cap`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct CollateralAsset {
+    pub mint: Pubkey,
+    pub oracle_feed: Pubkey,
+    pub decimals: u8,
+    pub ltv_bps: u16,
+    pub liquidation_threshold_bps: u16,
+    pub isolated: bool,
+    pub debt_ceiling: u64,
+    pub isolated_debt: u64,
+    pub emode_category: u8,
+    pub stake_pool: Pubkey,
+    pub supply_cap: u64,
+    pub total_supplied: u64,
+}
+
+/// Efficiency mode for assets correlated with SOL. Users who opt in borrow at
+/// `ltv_bps` against the category's basket assets (SOL collateral keeps the
+/// credit score LTV) and are liquidated at `liquidation_threshold` with
+/// `liquidation_bonus`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct EModeCategory {
+    pub id: u8,
+    pub ltv_bps: u16,
+    pub liquidation_threshold: u16,
+    pub liquidation_bonus: u16,
+}
+
+/// Amount of one basket asset a user has posted
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct CollateralPosition {
+    pub mint: Pubkey,
+    pub amount: u64,
+}
+
+/// A user's basket collateral in debt lamports: raw, LTV-weighted and
+/// liquidation-weighted, along with the price of each position
+#[derive(Clone, Copy, Default)]
+pub struct BasketValue {
+    pub collateral_value: u64,
+    pub borrowing_power: u64,
+    pub liquidation_value: u64,
+    pub prices: [u64; MAX_COLLATERAL_POSITIONS],
+}
+
+/// LTV band starting at `min_ltv`, with the rate premium and loan size cap that apply to it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, InitSpace)]
+pub struct RiskTier {
+    pub min_ltv: u16,
+    pub premium_bps: u16,
+    pub max_loan: u64,
+}
+
+impl LendingPool{
+    pub fn calculate_utilization(&mut self){
+        if self.total_deposits == 0 {
+            self.utilization_rate = 0;
+        }else {
+            self.utilization_rate = ((self.total_borrowed as u128 * 10000) / self.total_deposits as u128) as u16 
+        }
+    }
+
+    /// SOL deposits and custodied stake share the pool's supply cap
+    pub fn check_supply_cap(&self, amount: u64) -> Result<()> {
+        require!(
+            self.total_deposits + self.total_stake_collateral + amount <= self.supply_cap,
+            ArciLendError::SupplyCapExceeded
+        );
+
+        Ok(())
+    }
+
+    /// Price of one lamport of collateral in debt lamports, scaled by `PRICE_PRECISION`.
+    /// Collateral and debt are both native SOL, so the feed is checked against the
+    /// pool configuration and priced at parity.
+    pub fn collateral_price(&self, oracle_feed: &AccountInfo) -> Result<u64> {
+        require_keys_eq!(oracle_feed.key(), self.oracle_feed, ArciLendError::InvalidOracleFeed);
+
+        Ok(PRICE_PRECISION)
+    }
+
+    /// Accepts only Token-2022 mint extensions known not to move, freeze or
+    /// lock collateral behind the pool's back: transfer fees, metadata, and
+    /// transfer hooks run by allowlisted programs. Anything else, including
+    /// extensions this build doesn't know, is rejected. Classic SPL mints have none.
+    pub fn validate_mint_extensions(&self, mint: &AccountInfo) -> Result<()> {
+        if *mint.owner != spl_token_2022::ID {
+            return Ok(());
+        }
+
+        let data = mint.try_borrow_data()?;
+        let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+
+        for extension in state.get_extension_types()? {
+            match extension {
+                ExtensionType::TransferFeeConfig | ExtensionType::MetadataPointer | ExtensionType::TokenMetadata => {}
+                ExtensionType::TransferHook => {
+                    let hook = state.get_extension::<TransferHook>()?;
+                    if let Some(program_id) = Option::<Pubkey>::from(hook.program_id) {
+                        require!(
+                            self.transfer_hook_allowlist[..self.transfer_hook_count as usize].contains(&program_id),
+                            ArciLendError::TransferHookNotAllowed
+                        );
+                    }
+                }
+                _ => return err!(ArciLendError::UnsupportedMintExtension),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes off a defaulted credit line, returning the loss and the part the
+    /// loss reserve covered. Accrued interest is lost along with the principal.
+    pub fn write_off_credit_line(&mut self, credit_line: &CreditLine) -> (u64, u64) {
+        let loss = credit_line.total_owed();
+        let covered = loss.min(self.credit_line_loss_reserve);
+
+        self.credit_line_loss_reserve -= covered;
+        self.bad_debt += loss - covered;
+        self.credit_line_exposure -= credit_line.drawn;
+        self.total_borrowed = self.total_borrowed.saturating_sub(credit_line.drawn);
+
+        (loss, covered)
+    }
+
+    /// Reads `user`'s `Reputation` as kept by another pool program. The record
+    /// must be that program's canonical PDA and the program a trusted source.
+    pub fn imported_reputation(&self, record: &AccountInfo, user: &Pubkey) -> Result<Reputation> {
+        require!(
+            self.reputation_sources[..self.reputation_source_count as usize].contains(record.owner),
+            ArciLendError::InvalidReputationSource
+        );
+
+        let (expected, _) = Pubkey::find_program_address(&[REPUTATION_SEED, user.as_ref()], record.owner);
+        require_keys_eq!(record.key(), expected, ArciLendError::InvalidReputationSource);
+
+        let reputation = Reputation::try_deserialize(&mut &record.try_borrow_data()?[..])?;
+        require_keys_eq!(reputation.owner, *user, ArciLendError::InvalidReputationSource);
+
+        Ok(reputation)
+    }
+
+    pub fn collateral_asset(&self, mint: &Pubkey) -> Result<&CollateralAsset> {
+        self.collateral_assets[..self.collateral_asset_count as usize]
+            .iter()
+            .find(|asset| asset.mint == *mint)
+            .ok_or(error!(ArciLendError::UnsupportedCollateral))
+    }
+
+    pub fn collateral_asset_mut(&mut self, mint: &Pubkey) -> Result<&mut CollateralAsset> {
+        self.collateral_assets[..self.collateral_asset_count as usize]
+            .iter_mut()
+            .find(|asset| asset.mint == *mint)
+            .ok_or(error!(ArciLendError::UnsupportedCollateral))
+    }
+
+    /// Mint of the isolated asset in the user's basket, if any. Isolated
+    /// collateral is never mixed with other basket assets.
+    pub fn isolation_mint(&self, user_account: &UserAccount) -> Option<Pubkey> {
+        user_account
+            .collateral_positions()
+            .iter()
+            .find(|position| self.collateral_asset(&position.mint).is_ok_and(|asset| asset.isolated))
+            .map(|position| position.mint)
+    }
+
+    /// Borrowers backed by isolated collateral are capped by that asset's debt
+    /// ceiling, and may only borrow if SOL is approved for isolated borrowing.
+    /// Returns the isolated mint the debt was booked against, if any.
+    pub fn reserve_isolated_debt(&mut self, user_account: &UserAccount, amount: u64) -> Result<Pubkey> {
+        let Some(mint) = self.isolation_mint(user_account) else {
+            return Ok(Pubkey::default());
+        };
+
+        require!(self.isolated_borrowing_enabled, ArciLendError::IsolatedBorrowNotAllowed);
+
+        let asset = self.collateral_asset_mut(&mint)?;
+        require!(
+            asset.isolated_debt + amount <= asset.debt_ceiling,
+            ArciLendError::DebtCeilingExceeded
+        );
+        asset.isolated_debt += amount;
+
+        Ok(mint)
+    }
+
+    /// Returns repaid or written-off principal to the debt ceiling of the loan's isolated asset
+    pub fn release_isolated_debt(&mut self, loan: &Loan, principal: u64) {
+        if loan.isolated_mint == Pubkey::default() {
+            return;
+        }
+
+        if let Ok(asset) = self.collateral_asset_mut(&loan.isolated_mint) {
+            asset.isolated_debt = asset.isolated_debt.saturating_sub(principal);
+        }
+    }
+
+    pub fn emode_category(&self, id: u8) -> Result<EModeCategory> {
+        self.emode_categories[..self.emode_category_count as usize]
+            .iter()
+            .find(|category| category.id == id)
+            .copied()
+            .ok_or(error!(ArciLendError::InvalidEModeCategory))
+    }
+
+    /// Liquidation threshold, bonus and basket value counted toward solvency.
+    /// E-mode positions are liquidated on their category's terms, with the
+    /// basket counted at full value since every asset is correlated.
+    pub fn liquidation_terms(&self, user_account: &UserAccount, basket: &BasketValue) -> Result<(u16, u16, u64)> {
+        if user_account.emode_category != EMODE_NONE {
+            let category = self.emode_category(user_account.emode_category)?;
+            return Ok((category.liquidation_threshold, category.liquidation_bonus, basket.collateral_value));
+        }
+
+        Ok((self.liquidation_threshold, LIQUIDATION_BONUS, basket.liquidation_value))
+    }
+
+    /// Borrowing power of the user's SOL collateral and basket. SOL is always
+    /// weighted by the credit score LTV. In e-mode the category LTV applies to
+    /// the basket, after checking that every asset still belongs to the
+    /// category; otherwise basket assets are weighted by their own LTVs.
+    pub fn borrowing_power(&self, user_account: &UserAccount, basket: &BasketValue, now: i64) -> Result<u128> {
+        if user_account.emode_category != EMODE_NONE {
+            let category = self.emode_category(user_account.emode_category)?;
+            for position in user_account.collateral_positions() {
+                require!(
+                    self.collateral_asset(&position.mint)?.emode_category == category.id,
+                    ArciLendError::EModeCategoryMismatch
+                );
+            }
+
+            return Ok((user_account.native_collateral() as u128 * user_account.effective_ltv(now) as u128)
+                / BASIS_POINTS as u128
+                + basket.collateral_value as u128 * category.ltv_bps as u128 / BASIS_POINTS as u128);
+        }
+
+        Ok((user_account.native_collateral() as u128 * user_account.effective_ltv(now) as u128)
+            / BASIS_POINTS as u128
+            + basket.borrowing_power as u128)
+    }
+
+    /// Values every open basket position. `pricing_accounts` holds, in position
+    /// order, each asset's stake pool (if it has one) followed by its oracle
+    /// feed (if it has one).
+    /// Prices the user's basket from `pricing_accounts`, consuming one stake
+    /// pool and/or oracle feed per position and leaving the rest in the iterator
+    pub fn basket_value<'a, 'info>(
+        &self,
+        user_account: &UserAccount,
+        pricing_accounts: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+        clock: &Clock,
+    ) -> Result<BasketValue> {
+        let mut basket = BasketValue::default();
+
+        for (index, position) in user_account.collateral_positions().iter().enumerate() {
+            let asset = self.collateral_asset(&position.mint)?;
+            let price = asset_price(asset, pricing_accounts, clock)?;
+
+            let value = collateral_value(position.amount, price);
+            basket.prices[index] = price;
+            basket.collateral_value += value;
+            basket.borrowing_power += (value as u128 * asset.ltv_bps as u128 / BASIS_POINTS as u128) as u64;
+            basket.liquidation_value +=
+                (value as u128 * asset.liquidation_threshold_bps as u128 / BASIS_POINTS as u128) as u64;
+        }
+
+        Ok(basket)
+    }
+
+    /// Highest band whose `min_ltv` the given LTV reaches. Used for pricing any
+    /// new or repriced loan.
+    pub fn risk_tier(&self, risk_adjusted_ltv: u16) -> RiskTier {
+        self.risk_tiers[..self.risk_tier_count as usize]
+            .iter()
+            .rev()
+            .find(|tier| risk_adjusted_ltv >= tier.min_ltv)
+            .copied()
+            .unwrap_or(self.risk_tiers[0])
+    }
+
+    /// Identity of the node slot `key` signs for at `now`, if it is accepted.
+    /// During a rotation the old and new keys share the old key's identity so
+    /// one node cannot attest twice.
+    pub fn mpc_node_id(&self, key: &Pubkey, now: i64) -> Option<Pubkey> {
+        let rotation = &self.mpc_key_rotation;
+
+        if rotation.old_key != Pubkey::default() {
+            if *key == rotation.old_key {
+                return (now < rotation.overlap_ends_at).then_some(rotation.old_key);
+            }
+            if *key == rotation.new_key {
+                return (now >= rotation.activates_at).then_some(rotation.old_key);
+            }
+        }
+
+        self.mpc_nodes[..self.mpc_node_count as usize]
+            .contains(key)
+            .then_some(*key)
+    }
+
+    /// Counts distinct registered MPC nodes whose signature over `message` was
+    /// verified by an Ed25519 precompile instruction in this transaction
+    pub fn count_mpc_attestations(&self, instructions: &AccountInfo, message: &[u8], now: i64) -> Result<usize> {
+        let mut signers: Vec<Pubkey> = Vec::new();
+        let mut index = 0;
+
+        while let Ok(ix) = load_instruction_at_checked(index, instructions) {
+            index += 1;
+
+            if ix.program_id != ed25519_program::ID {
+                continue;
+            }
+
+            let data = &ix.data;
+            let num_signatures = data.first().copied().unwrap_or(0) as usize;
+
+            for i in 0..num_signatures {
+                let start = ED25519_HEADER_LEN + i * ED25519_OFFSETS_LEN;
+                let Some(offsets) = data.get(start..start + ED25519_OFFSETS_LEN) else {
+                    break;
+                };
+                let read = |at: usize| u16::from_le_bytes([offsets[at], offsets[at + 1]]);
+
+                // Key and message must live in the precompile's own data, otherwise
+                // the verified bytes could differ from what is read here
+                if read(2) != u16::MAX || read(6) != u16::MAX || read(12) != u16::MAX {
+                    continue;
+                }
+
+                let pubkey_offset = read(4) as usize;
+                let message_offset = read(8) as usize;
+                let message_size = read(10) as usize;
+
+                let pubkey = data
+                    .get(pubkey_offset..pubkey_offset + 32)
+                    .and_then(|key| <[u8; 32]>::try_from(key).ok())
+                    .map(Pubkey::new_from_array);
+                let signed = data.get(message_offset..message_offset + message_size);
+
+                if let (Some(pubkey), Some(signed)) = (pubkey, signed) {
+                    if signed != message {
+                        continue;
+                    }
+                    if let Some(node_id) = self.mpc_node_id(&pubkey, now) {
+                        if !signers.contains(&node_id) {
+                            signers.push(node_id);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(signers.len())
+    }
+
+    pub fn flash_fee(&self, amount: u64) -> u64 {
+        ((amount as u128 * self.flash_fee_bps as u128) / BASIS_POINTS as u128) as u64
+    }
+
+    pub fn get_curent_interest_rate(&self) -> u16 {
+        let base_rate = self.interest_rate;
+        let optimal = 8000;
+
+        if self.utilization_rate <= optimal {
+            base_rate
+        }else{
+            let excess = self.utilization_rate - optimal;
+            base_rate + (excess * 5)
+        }
+    }
+}
+
+/// Value of `amount` collateral lamports in debt lamports at `price`
+pub fn collateral_value(amount: u64, price: u64) -> u64 {
+    ((amount as u128 * price as u128) / PRICE_PRECISION as u128) as u64
+}
+
+/// Canonical message MPC nodes sign to attest a credit score update
+pub fn score_update_message(
+    user: &Pubkey,
+    encrypted_score: &[u8; 32],
+    risk_adjusted_ltv: u16,
+    nonce: u64,
+    expiry: i64
+) -> Vec<u8> {
+    let mut message = SCORE_UPDATE_DOMAIN.to_vec();
+    message.extend_from_slice(user.as_ref());
+    message.extend_from_slice(encrypted_score);
+    message.extend_from_slice(&risk_adjusted_ltv.to_le_bytes());
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message.extend_from_slice(&expiry.to_le_bytes());
+    message
+}
+
+/// Canonical message MPC nodes sign to attest a score re-encryption
+pub fn score_disclosure_message(
+    user: &Pubkey,
+    recipient_key: &[u8; 32],
+    source_score: &[u8; 32],
+    ciphertext: &[u8; 32],
+    nonce: u128,
+    expiry: i64
+) -> Vec<u8> {
+    let mut message = SCORE_DISCLOSURE_DOMAIN.to_vec();
+    message.extend_from_slice(user.as_ref());
+    message.extend_from_slice(recipient_key);
+    message.extend_from_slice(source_score);
+    message.extend_from_slice(ciphertext);
+    message.extend_from_slice(&nonce.to_le_bytes());
+    message.extend_from_slice(&expiry.to_le_bytes());
+    message
+}
+
+/// Canonical message MPC nodes sign to attest a score threshold comparison
+pub fn score_threshold_message(
+    user: &Pubkey,
+    encrypted_score: &[u8; 32],
+    threshold: u16,
+    meets_threshold: bool,
+    expiry: i64
+) -> Vec<u8> {
+    let mut message = SCORE_THRESHOLD_DOMAIN.to_vec();
+    message.extend_from_slice(user.as_ref());
+    message.extend_from_slice(encrypted_score);
+    message.extend_from_slice(&threshold.to_le_bytes());
+    message.push(meets_threshold as u8);
+    message.extend_from_slice(&expiry.to_le_bytes());
+    message
+}
+
+/// Simple interest on `principal` at an annual `rate_bps` over `time_elapsed` seconds
+pub fn simple_interest(principal: u64, rate_bps: u16, time_elapsed: i64) -> u64 {
+    let seconds_per_year = 365 * 24 * 60 * 60;
+
+    let interest = (principal as u128 * rate_bps as u128 * time_elapsed as u64 as u128)
+        / (seconds_per_year as u128 * BASIS_POINTS as u128);
+
+    interest as u64
+}
+
+/// Moves `amount` of a basket position out of its vault, signed by the pool.
+/// `accounts` holds the mint, the vault, the destination token account and
+/// the token program; `hook_accounts` are forwarded for transfer hooks.
+pub fn seize_basket_position<'info>(
+    lending_pool: &Account<'info, LendingPool>,
+    asset: &CollateralAsset,
+    accounts: &[AccountInfo<'info>],
+    hook_accounts: &[AccountInfo<'info>],
+    amount: u64,
+) -> Result<()> {
+    let [mint, vault, destination, token_program] = accounts else {
+        return err!(ArciLendError::InvalidCollateralAsset);
+    };
+    let (expected_vault, _) = Pubkey::find_program_address(&[COLLATERAL_VAULT_SEED, asset.mint.as_ref()], &crate::ID);
+
+    require!(
+        mint.key() == asset.mint && vault.key() == expected_vault && token_program.key() == *mint.owner,
+        ArciLendError::InvalidCollateralAsset
+    );
+
+    let signer_seeds: &[&[&[u8]]] = &[&[LENDING_POOL_SEED, &[lending_pool.bump]]];
+    let cpi_context = CpiContext::new_with_signer(
+        token_program.clone(),
+        token_interface::TransferChecked {
+            from: vault.clone(),
+            mint: mint.clone(),
+            to: destination.clone(),
+            authority: lending_pool.to_account_info(),
+        },
+        signer_seeds,
+    )
+    .with_remaining_accounts(hook_accounts.to_vec());
+
+    token_interface::transfer_checked(cpi_context, amount, asset.decimals)
+}
+
+/// Price of a basket asset. Stake pool tokens are priced at the pool's exchange
+/// rate, capped by the market oracle when one is configured; anything else
+/// uses the market oracle alone.
+pub fn asset_price<'a, 'info>(
+    asset: &CollateralAsset,
+    pricing_accounts: &mut std::slice::Iter<'a, AccountInfo<'info>>,
+    clock: &Clock,
+) -> Result<u64> {
+    let mut next_account = |expected: &Pubkey| -> Result<&'a AccountInfo<'info>> {
+        let account = pricing_accounts.next().ok_or(ArciLendError::InvalidOracleFeed)?;
+        require_keys_eq!(account.key(), *expected, ArciLendError::InvalidOracleFeed);
+        Ok(account)
+    };
+
+    let exchange_rate = if asset.stake_pool != Pubkey::default() {
+        Some(stake_pool_price(next_account(&asset.stake_pool)?, &asset.mint, clock.epoch)?)
+    } else {
+        None
+    };
+    let market_price = if asset.oracle_feed != Pubkey::default() {
+        Some(oracle_price(next_account(&asset.oracle_feed)?, asset.decimals, clock.unix_timestamp)?)
+    } else {
+        None
+    };
+
+    match (exchange_rate, market_price) {
+        (Some(rate), Some(market)) => Ok(rate.min(market)),
+        (Some(price), None) | (None, Some(price)) => Ok(price),
+        (None, None) => err!(ArciLendError::InvalidOracleFeed),
+    }
+}
+
+/// Lamports per pool token base unit from an SPL stake pool's
+/// `total_lamports / pool_token_supply`, scaled by `PRICE_PRECISION`.
+/// The pool must have been updated in the current epoch.
+pub fn stake_pool_price(stake_pool: &AccountInfo, pool_mint: &Pubkey, epoch: u64) -> Result<u64> {
+    require_keys_eq!(*stake_pool.owner, SPL_STAKE_POOL_PROGRAM_ID, ArciLendError::InvalidStakePool);
+
+    let data = stake_pool.try_borrow_data()?;
+    let fields = data
+        .get(..STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET + 8)
+        .ok_or(ArciLendError::InvalidStakePool)?;
+
+    require!(
+        fields[0] == STAKE_POOL_ACCOUNT_TYPE
+            && fields[STAKE_POOL_MINT_OFFSET..STAKE_POOL_MINT_OFFSET + 32] == pool_mint.to_bytes(),
+        ArciLendError::InvalidStakePool
+    );
+
+    let read_u64 = |offset: usize| u64::from_le_bytes(fields[offset..offset + 8].try_into().unwrap());
+    let total_lamports = read_u64(STAKE_POOL_TOTAL_LAMPORTS_OFFSET);
+    let pool_token_supply = read_u64(STAKE_POOL_TOKEN_SUPPLY_OFFSET);
+
+    require!(
+        read_u64(STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET) == epoch,
+        ArciLendError::StakePoolNotUpdated
+    );
+    require!(pool_token_supply > 0, ArciLendError::InvalidStakePool);
+
+    u64::try_from(total_lamports as u128 * PRICE_PRECISION as u128 / pool_token_supply as u128)
+        .ok()
+        .filter(|price| *price > 0)
+        .ok_or(error!(ArciLendError::InvalidOraclePrice))
+}
+
+/// Reads a Pyth `PriceUpdateV2` account quoting the asset in SOL and returns
+/// debt lamports per base unit of collateral, scaled by `PRICE_PRECISION`
+pub fn oracle_price(oracle_feed: &AccountInfo, decimals: u8, now: i64) -> Result<u64> {
+    require_keys_eq!(*oracle_feed.owner, PYTH_RECEIVER_PROGRAM_ID, ArciLendError::InvalidOracleFeed);
+
+    let data = oracle_feed.try_borrow_data()?;
+
+    // Only fully verified updates are accepted
+    require!(
+        data.get(PYTH_VERIFICATION_LEVEL_OFFSET) == Some(&PYTH_VERIFICATION_FULL),
+        ArciLendError::InvalidOracleFeed
+    );
+    let message = data
+        .get(PYTH_PRICE_OFFSET..PYTH_PRICE_OFFSET + 28)
+        .ok_or(ArciLendError::InvalidOracleFeed)?;
+
+    let price = i64::from_le_bytes(message[0..8].try_into().unwrap());
+    let exponent = i32::from_le_bytes(message[16..20].try_into().unwrap());
+    let publish_time = i64::from_le_bytes(message[20..28].try_into().unwrap());
+
+    require!(price > 0, ArciLendError::InvalidOraclePrice);
+    require!(now - publish_time <= MAX_ORACLE_PRICE_AGE, ArciLendError::StaleOraclePrice);
+
+    // SOL per token -> lamports per base unit (9 decimals) -> PRICE_PRECISION (9 decimals)
+    let scale = exponent + 18 - decimals as i32;
+    let price = if scale >= 0 {
+        10u128
+            .checked_pow(scale as u32)
+            .and_then(|factor| (price as u128).checked_mul(factor))
+    } else {
+        10u128.checked_pow(scale.unsigned_abs()).map(|factor| price as u128 / factor)
+    };
+
+    price
+        .and_then(|price| u64::try_from(price).ok())
+        .filter(|price| *price > 0)
+        .ok_or(error!(ArciLendError::InvalidOraclePrice))
+}
+
+/// Collateral lamports needed to cover `value` debt lamports at `price`, rounded up
+pub fn collateral_for_value(value: u64, price: u64) -> u64 {
+    (value as u128 * PRICE_PRECISION as u128).div_ceil(price as u128) as u64
+}
+
+/// Collateral a liquidator takes for paying off `debt` at `price`: the debt's
+/// value plus the liquidation bonus, limited to what is `available`
+pub fn liquidation_seizure(debt: u64, liquidation_bonus: u16, price: u64, available: u64) -> u64 {
+    let with_bonus = (debt as u128 * (BASIS_POINTS + liquidation_bonus) as u128 / BASIS_POINTS as u128) as u64;
+    collateral_for_value(with_bonus, price).min(available)
+}
+
+/// Lamports of a seized stake account beyond `uncovered` debt plus the
+/// liquidation bonus; the liquidator pays them back to the borrower since
+/// stake accounts are taken whole
+pub fn stake_surplus_value(stake_lamports: u64, uncovered: u64, liquidation_bonus: u16, price: u64) -> u64 {
+    stake_lamports.saturating_sub(liquidation_seizure(uncovered, liquidation_bonus, price, u64::MAX))
+}
+
+/// Debt paid off by collateral worth `value` once the liquidation bonus is taken out of it
+pub fn debt_covered(value: u64, liquidation_bonus: u16) -> u64 {
+    (value as u128 * BASIS_POINTS as u128 / (BASIS_POINTS + liquidation_bonus) as u128) as u64
+}
+
+/// Collateral value over debt in basis points; a position without debt is always healthy
+pub fn health_factor(collateral_value: u64, debt: u64) -> u64 {
+    if debt == 0 {
+        return u64::MAX;
+    }
+
+    ((collateral_value as u128 * BASIS_POINTS as u128) / debt as u128).min(u64::MAX as u128) as u64
+}
+
+/// Whether `remaining_value` of collateral still covers `debt` at `min_health` after a withdrawal
+pub fn is_withdrawal_healthy(remaining_value: u64, debt: u64, min_health: u16) -> bool {
+    health_factor(remaining_value, debt) >= min_health as u64
+}
+
+/// ---- USer Account Struct ----
+
+#[account]
+#[derive(InitSpace)]
+pub struct UserAccount{
+    pub owner: Pubkey,
+    pub collateral_deposited: u64,
+    pub amount_borrowed: u64,
+    pub last_update: i64,
+    pub loan_count: u8,
+    pub encrypted_credit_score: [u8; 32],
+    pub risk_adjusted_ltv: u16,
+    pub successful_repayments: u16,
+    pub defaults: u16,
+    pub bump: u8,
+    pub credit_score_expires_at: i64,
+    pub total_repaid_volume: u64,
+    pub closed_loans: u16,
+    pub total_loan_duration: i64,
+    pub late_payments: u16,
+    pub max_utilization_bps: u16,
+    pub first_deposit_at: i64,
+    pub liquidations: u16,
+    pub credit_delegated: u64,
+    pub delegated_debt: u64,
+    pub amount_guaranteed: u64,
+    pub collateral_positions: [CollateralPosition; MAX_COLLATERAL_POSITIONS],
+    pub collateral_position_count: u8,
+    pub emode_category: u8,
+    pub stake_collateral: u64,
+    // Principal drawn on the user's credit line, counted toward `max_user_borrow`
+    pub credit_line_drawn: u64,
+}
+
+impl UserAccount {
+    pub fn credit_history(&self, now: i64) -> CreditHistory {
+        let average_loan_duration = self
+            .total_loan_duration
+            .checked_div(self.closed_loans as i64)
+            .unwrap_or(0);
+
+        CreditHistory {
+            collateral_deposited: self.collateral_deposited,
+            amount_borrowed: self.amount_borrowed,
+            successful_repayments: self.successful_repayments,
+            defaults: self.defaults,
+            total_repaid_volume: self.total_repaid_volume,
+            average_loan_duration,
+            late_payments: self.late_payments,
+            max_utilization_bps: self.max_utilization_bps,
+            account_age: now - self.first_deposit_at,
+            liquidations: self.liquidations,
+        }
+    }
+
+    pub fn collateral_positions(&self) -> &[CollateralPosition] {
+        &self.collateral_positions[..self.collateral_position_count as usize]
+    }
+
+    pub fn add_collateral_position(&mut self, mint: Pubkey, amount: u64) -> Result<()> {
+        let count = self.collateral_position_count as usize;
+
+        match self.collateral_positions[..count].iter().position(|p| p.mint == mint) {
+            Some(index) => self.collateral_positions[index].amount += amount,
+            None => {
+                require!(count < MAX_COLLATERAL_POSITIONS, ArciLendError::TooManyCollateralPositions);
+                self.collateral_positions[count] = CollateralPosition { mint, amount };
+                self.collateral_position_count += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reduces a position, closing it once empty while keeping the order of the rest
+    pub fn remove_collateral_position(&mut self, mint: &Pubkey, amount: u64) -> Result<()> {
+        let count = self.collateral_position_count as usize;
+        let index = self.collateral_positions[..count]
+            .iter()
+            .position(|p| p.mint == *mint)
+            .ok_or(ArciLendError::UnsupportedCollateral)?;
+
+        let position = &mut self.collateral_positions[index];
+        require!(amount > 0 && amount <= position.amount, ArciLendError::InsufficientBalance);
+        position.amount -= amount;
+
+        if position.amount == 0 {
+            self.collateral_positions.copy_within(index + 1..count, index);
+            self.collateral_positions[count - 1] = CollateralPosition::default();
+            self.collateral_position_count -= 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn import_reputation(&mut self, reputation: &Reputation) {
+        self.successful_repayments = reputation.successful_repayments;
+        self.defaults = reputation.defaults;
+        self.liquidations = reputation.liquidations;
+        self.total_repaid_volume = reputation.total_repaid_volume;
+    }
+
+    /// Updates repayment history after `amount` was applied to `loan`.
+    /// A loan counts as late once, on its first payment after `due_at`.
+    pub fn record_repayment(&mut self, loan: &mut Loan, amount: u64, now: i64) {
+        let loan_age = now - loan.start_time;
+
+        self.total_repaid_volume += amount;
+
+        if now > loan.due_at && !loan.late_payment_recorded {
+            self.late_payments += 1;
+            loan.late_payment_recorded = true;
+        }
+
+        if loan.borrowed_amount == 0 {
+            self.successful_repayments += 1;
+            self.closed_loans += 1;
+            self.total_loan_duration += loan_age;
+        }
+    }
+
+    /// Takes `amount` of SOL collateral out, keeping the amount recorded on the
+    /// loan within what is still deposited
+    pub fn withdraw_collateral(&mut self, amount: u64, loan: Option<&mut Loan>) {
+        self.collateral_deposited -= amount;
+
+        if let Some(loan) = loan {
+            loan.collateral_amount = loan.collateral_amount.min(self.collateral_deposited);
+        }
+    }
+
+    /// SOL collateral a liquidation of `loan` can take. The amount recorded on
+    /// the loan goes stale once collateral is seized for debt it guaranteed or
+    /// delegated, so it is capped by what is still deposited.
+    pub fn seizable_collateral(&self, loan: &Loan) -> u64 {
+        loan.collateral_amount.min(self.collateral_deposited)
+    }
+
+    /// Risk-adjusted LTV, falling back to the default once the score has expired
+    pub fn effective_ltv(&self, now: i64) -> u16 {
+        if now <= self.credit_score_expires_at {
+            self.risk_adjusted_ltv
+        } else {
+            DEFAULT_LTV
+        }
+    }
+
+    /// Pool-held SOL plus the delegated lamports of a custodied stake account
+    pub fn native_collateral(&self) -> u64 {
+        self.collateral_deposited + self.stake_collateral
+    }
+
+    /// Own debt plus capacity delegated to or guaranteed for other borrowers
+    pub fn committed_credit(&self) -> u64 {
+        self.amount_borrowed + self.credit_delegated + self.amount_guaranteed
+    }
+
+    /// `extra_collateral` covers value backing the debt outside the SOL balance:
+    /// guarantees and liquidation-weighted basket positions. Debt drawn by
+    /// delegatees against this account's collateral counts as its own.
+    pub fn is_liquidatable(&self, _price: u64, liquidation_threshold: u16, extra_collateral: u64) -> bool {
+        let debt = self.amount_borrowed + self.delegated_debt;
+        if debt == 0 {
+            return false;
+        }
+
+        let collateral_value = self.native_collateral() + extra_collateral;
+        let debt_threshold = (debt as u128 * liquidation_threshold as u128) / 10000;
+
+        collateral_value < debt_threshold as u64
+    }
+}
+
+/// ---- Credit Score Computation Struct ----
+
+#[account]
+#[derive(InitSpace)]
+pub struct CreditScoreComputation {
+    pub user: Pubkey,
+    pub computation_offset: u64,
+    pub request_nonce: u64,
+    pub pending: bool,
+    pub queued_at: i64,
+    pub deadline: i64,
+    // Snapshot emitted in `CreditScoreRequested`
+    pub snapshot: CreditHistory,
+    pub fee_paid: u64,
+    pub bump: u8,
 }
 
-/// ---- Event ----
-#[event]
-pub struct CreditScoreRequested {
+/// ---- Score Disclosure Struct ----
+
+#[account]
+#[derive(InitSpace)]
+pub struct ScoreDisclosure {
     pub user: Pubkey,
-    pub collateral_deposited: u64,
-    pub amount_borrowed: u64,
+    pub recipient_key: [u8; 32],
+    pub source_score: [u8; 32],
+    pub ciphertext: [u8; 32],
+    pub nonce: u128,
+    pub pending: bool,
+    pub requested_at: i64,
+    pub bump: u8,
+}
+
+/// ---- Reputation Struct ----
+// Pool-independent credit history for a borrower, imported by any pool when
+// it opens a fresh `UserAccount` for them
+
+#[account]
+#[derive(InitSpace)]
+pub struct Reputation {
+    pub owner: Pubkey,
     pub successful_repayments: u16,
     pub defaults: u16,
-    pub timestamp: i64,
+    pub liquidations: u16,
+    pub total_repaid_volume: u64,
+    pub last_update: i64,
+    pub bump: u8,
 }
 
-/// ---- Lending Pool Struct ----
+impl Reputation {
+    pub fn import(&mut self, other: &Reputation) {
+        self.successful_repayments = other.successful_repayments;
+        self.defaults = other.defaults;
+        self.liquidations = other.liquidations;
+        self.total_repaid_volume = other.total_repaid_volume;
+    }
+
+    pub fn record_repayment(&mut self, loan: &Loan, amount: u64, now: i64) {
+        self.total_repaid_volume += amount;
+        if loan.borrowed_amount == 0 {
+            self.successful_repayments += 1;
+        }
+        self.last_update = now;
+    }
+
+    pub fn record_liquidation(&mut self, now: i64) {
+        self.defaults += 1;
+        self.liquidations += 1;
+        self.last_update = now;
+    }
+}
+
+/// ---- Credit Line Struct ----
 
 #[account]
 #[derive(InitSpace)]
-pub struct LendingPool{
-    pub authority: Pubkey,
-    pub total_deposits: u64,
-    pub total_borrowed: u64,
+pub struct CreditLine {
+    pub owner: Pubkey,
+    pub limit: u64,
+    pub drawn: u64,
+    pub accrued_interest: u64,
     pub interest_rate: u16,
-    pub collateral_ratio: u16,
-    pub liquidation_threshold: u16,
-    pub arcium_mcp_pubkey: Pubkey,
-    pub oracle_feed: Pubkey,
+    pub last_accrual: i64,
+    pub due_at: i64,
+    pub defaulted: bool,
     pub bump: u8,
-    pub utilization_rate: u16,
-    pub total_fees: u64,
 }
 
-impl LendingPool{
-    pub fn calculate_utilization(&mut self){
-        if self.total_deposits == 0 {
-            self.utilization_rate = 0;
-        }else {
-            self.utilization_rate = ((self.total_borrowed as u128 * 10000) / self.total_deposits as u128) as u16 
-        }
+impl CreditLine {
+    pub fn accrue_interest(&mut self, current_time: i64) {
+        self.accrued_interest += simple_interest(self.drawn, self.interest_rate, current_time - self.last_accrual);
+        self.last_accrual = current_time;
     }
 
-    pub fn get_curent_interest_rate(&self) -> u16 {
-        let base_rate = self.interest_rate;
-        let optimal = 8000;
+    /// Applies a payment to accrued interest first and returns the principal repaid
+    pub fn apply_repayment(&mut self, amount: u64) -> u64 {
+        let interest_payment = amount.min(self.accrued_interest);
+        let principal_payment = amount - interest_payment;
 
-        if self.utilization_rate <= optimal {
-            base_rate
-        }else{
-            let excess = self.utilization_rate - optimal;
-            base_rate + (excess * 5)
-        }
+        self.accrued_interest -= interest_payment;
+        self.drawn -= principal_payment;
+
+        principal_payment
+    }
+
+    pub fn total_owed(&self) -> u64 {
+        self.drawn + self.accrued_interest
     }
 }
 
-/// ---- USer Account Struct ----
+/// ---- Stake Collateral Struct ----
+
+// Native stake account held in custody for `owner`, valued at `lamports`
 
 #[account]
 #[derive(InitSpace)]
-pub struct UserAccount{
+pub struct StakeCollateral {
     pub owner: Pubkey,
-    pub collateral_deposited: u64,
-    pub amount_borrowed: u64,
-    pub last_update: i64,
-    pub loan_count: u8,
-    pub encrypted_credit_score: [u8; 32],
-    pub risk_adjusted_ltv: u16,
-    pub successful_repayments: u16,
-    pub defaults: u16,
-    pub bump: u8
+    pub stake_account: Pubkey,
+    pub lamports: u64,
+    pub bump: u8,
 }
 
-impl UserAccount {
-    pub fn is_liquidatable(&self, _price: u64, liquidation_threshold: u16) -> bool {
-        if self.amount_borrowed == 0 {
-            return false;
-        }
+/// Moves both the stake and withdraw authority of `stake_account` from
+/// `authority` to `new_authority`
+pub fn authorize_stake<'info>(
+    stake_program: AccountInfo<'info>,
+    stake_account: AccountInfo<'info>,
+    authority: AccountInfo<'info>,
+    new_authority: AccountInfo<'info>,
+    clock: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    for stake_authorize in [StakeAuthorize::Staker, StakeAuthorize::Withdrawer] {
+        let cpi_context = CpiContext::new_with_signer(
+            stake_program.clone(),
+            stake::Authorize {
+                stake: stake_account.clone(),
+                authorized: authority.clone(),
+                new_authorized: new_authority.clone(),
+                clock: clock.clone(),
+            },
+            signer_seeds,
+        );
+        stake::authorize(cpi_context, stake_authorize, None)?;
+    }
+
+    Ok(())
+}
 
-        let collateral_value = self.collateral_deposited;
-        let debt_threshold = (self.amount_borrowed as u128 * liquidation_threshold as u128) / 10000;
+/// ---- Credit Delegation Struct ----
 
-        collateral_value < debt_threshold as u64
+// Lets `delegatee` borrow up to `cap` against the delegator's collateral
+
+#[account]
+#[derive(InitSpace)]
+pub struct CreditDelegation {
+    pub delegator: Pubkey,
+    pub delegatee: Pubkey,
+    pub cap: u64,
+    pub expiry: i64,
+    pub used: u64,
+    pub bump: u8,
+}
+
+/// Returns repaid principal on a delegated loan to its delegation and delegator
+pub fn release_delegated_debt(
+    loan: &Loan,
+    principal_repaid: u64,
+    credit_delegation: Option<&mut Account<CreditDelegation>>,
+    delegator_account: Option<&mut Account<UserAccount>>,
+) -> Result<()> {
+    if loan.delegation == Pubkey::default() {
+        return Ok(());
+    }
+
+    let credit_delegation = credit_delegation.ok_or(ArciLendError::InvalidDelegation)?;
+    let delegator_account = delegator_account.ok_or(ArciLendError::InvalidDelegation)?;
+
+    require!(
+        credit_delegation.key() == loan.delegation
+            && delegator_account.owner == credit_delegation.delegator,
+        ArciLendError::InvalidDelegation
+    );
+
+    credit_delegation.used = credit_delegation.used.saturating_sub(principal_repaid);
+    delegator_account.delegated_debt = delegator_account.delegated_debt.saturating_sub(principal_repaid);
+
+    Ok(())
+}
+
+/// Releases the part of a guarantee no longer needed to cover the loan's principal
+pub fn release_guarantee(
+    loan: &mut Loan,
+    guarantor_account: Option<&mut Account<UserAccount>>,
+) -> Result<()> {
+    if loan.guarantor == Pubkey::default() || loan.borrowed_amount >= loan.guaranteed_amount {
+        return Ok(());
     }
+
+    let guarantor_account = guarantor_account.ok_or(ArciLendError::InvalidGuarantee)?;
+    require!(guarantor_account.owner == loan.guarantor, ArciLendError::InvalidGuarantee);
+
+    let released = loan.guaranteed_amount - loan.borrowed_amount;
+    guarantor_account.amount_guaranteed = guarantor_account.amount_guaranteed.saturating_sub(released);
+    loan.guaranteed_amount = loan.borrowed_amount;
+
+    Ok(())
 }
 
 /// ---- LOAN Struct ----
@@ -599,20 +4550,31 @@ pub struct Loan {
     pub accrued_interest: u64,
     pub is_liquidated: bool,
     pub bump: u8,
+    pub delegation: Pubkey,
+    pub guarantor: Pubkey,
+    pub guaranteed_amount: u64,
+    pub isolated_mint: Pubkey,
+    pub due_at: i64,
+    pub late_payment_recorded: bool,
 }
 
 impl Loan {
     pub fn accrue_interest(&mut self, current_time: i64) {
-        let time_elapsed = (current_time - self.last_accrual) as u64;
-        let seconds_per_year = 365 * 24 * 60 * 60;
-        
-        let interest = (self.borrowed_amount as u128 * self.interest_rate as u128 * time_elapsed as u128)
-            / (seconds_per_year as u128 * 10000);
-        
-        self.accrued_interest += interest as u64;
+        self.accrued_interest += simple_interest(self.borrowed_amount, self.interest_rate, current_time - self.last_accrual);
         self.last_accrual = current_time;
     }
 
+    /// Applies a payment to accrued interest first and returns the principal repaid
+    pub fn apply_repayment(&mut self, amount: u64) -> u64 {
+        let interest_payment = amount.min(self.accrued_interest);
+        let principal_payment = amount - interest_payment;
+
+        self.accrued_interest -= interest_payment;
+        self.borrowed_amount -= principal_payment;
+
+        principal_payment
+    }
+
     pub fn total_owed(&self) -> u64 {
         self.borrowed_amount + self.accrued_interest
     }
@@ -621,13 +4583,60 @@ impl Loan {
 pub const LENDING_POOL_SEED: &[u8] = b"lending_pool";
 pub const USER_ACCOUNT_SEED: &[u8] = b"user_account";
 pub const LOAN_SEED: &[u8] = b"loan";
+pub const CREDIT_COMPUTATION_SEED: &[u8] = b"credit_computation";
+pub const MXE_SIGNER_SEED: &[u8] = b"mxe_signer";
+pub const REPUTATION_SEED: &[u8] = b"reputation";
+pub const CREDIT_LINE_SEED: &[u8] = b"credit_line";
+pub const SCORE_DISCLOSURE_SEED: &[u8] = b"score_disclosure";
+pub const DELEGATION_SEED: &[u8] = b"delegation";
+pub const COLLATERAL_VAULT_SEED: &[u8] = b"collateral_vault";
+pub const STAKE_COLLATERAL_SEED: &[u8] = b"stake_collateral";
+pub const SCORE_UPDATE_DOMAIN: &[u8] = b"arcilend:score_update";
+pub const SCORE_DISCLOSURE_DOMAIN: &[u8] = b"arcilend:score_disclosure";
+pub const SCORE_THRESHOLD_DOMAIN: &[u8] = b"arcilend:score_threshold";
 
 pub const MIN_COLLATERAL_RATIO: u16 = 12000;
 pub const MAX_COLLATERAL_RATIO: u16 = 30000;
 pub const LIQUIDATION_BONUS: u16 = 500;
+pub const MAX_EMODE_LIQUIDATION_BONUS: u16 = 1000;
+pub const MAX_EMODE_CATEGORIES: usize = 4;
+pub const EMODE_NONE: u8 = 0;
 pub const MIN_LTV: u16 = 5000;
+pub const DEFAULT_LTV: u16 = 5000;
 pub const MAX_LTV: u16 = 8000;
 pub const BASIS_POINTS: u16 = 10000;
+pub const PRICE_PRECISION: u64 = 1_000_000_000;
+pub const DEFAULT_FLASH_FEE_BPS: u16 = 9;
+pub const MAX_FLASH_FEE_BPS: u16 = 100;
+pub const DELEVERAGE_FEE_BPS: u16 = 50;
+pub const MAX_MPC_NODES: usize = 7;
+pub const MAX_RISK_TIERS: usize = 8;
+pub const MAX_MPC_KEY_OVERLAP: i64 = 7 * 24 * 60 * 60;
+pub const MAX_RESERVE_FEE_BPS: u16 = 1000;
+pub const CREDIT_SCORE_REQUEST_TTL: i64 = 10 * 60;
+pub const CREDIT_SCORE_VALIDITY: i64 = 30 * 24 * 60 * 60;
+pub const MAX_CREDIT_SCORE_COOLDOWN: i64 = 7 * 24 * 60 * 60;
+pub const LOAN_TERM: i64 = 90 * 24 * 60 * 60;
+pub const MAX_COLLATERAL_ASSETS: usize = 8;
+pub const MAX_COLLATERAL_POSITIONS: usize = 4;
+pub const MAX_ORACLE_PRICE_AGE: i64 = 60;
+pub const MAX_TRANSFER_HOOK_PROGRAMS: usize = 4;
+pub const MAX_REPUTATION_SOURCES: usize = 4;
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+pub const SPL_STAKE_POOL_PROGRAM_ID: Pubkey = pubkey!("SPoo1Ku8WFXoNDMHPsrGSTSG1Y47rzgn41SLUNakuHy");
+const ED25519_HEADER_LEN: usize = 2;
+const ED25519_OFFSETS_LEN: usize = 14;
+// PriceUpdateV2: discriminator, write authority, verification level, then the price message
+const PYTH_VERIFICATION_LEVEL_OFFSET: usize = 8 + 32;
+const PYTH_VERIFICATION_FULL: u8 = 1;
+const PYTH_PRICE_OFFSET: usize = PYTH_VERIFICATION_LEVEL_OFFSET + 1 + 32;
+// StakePool: account type, three authorities, withdraw bump seed, then
+// validator list, reserve stake and pool mint, fee account, token program
+const STAKE_POOL_ACCOUNT_TYPE: u8 = 1;
+const STAKE_POOL_MINT_OFFSET: usize = 1 + 3 * 32 + 1 + 2 * 32;
+const STAKE_POOL_TOTAL_LAMPORTS_OFFSET: usize = STAKE_POOL_MINT_OFFSET + 3 * 32;
+const STAKE_POOL_TOKEN_SUPPLY_OFFSET: usize = STAKE_POOL_TOTAL_LAMPORTS_OFFSET + 8;
+const STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET: usize = STAKE_POOL_TOKEN_SUPPLY_OFFSET + 8;
 
 /// ---- ERRORs ----
 #[error_code]
@@ -656,4 +4665,665 @@ pub enum ArciLendError {
     InvalidCreditScore,
     #[msg("Exceeds risk-adjusted LTV")]
     ExceedsRiskAdjustedLTV,
-}
\ No newline at end of file
+    #[msg("Withdrawal would drop health factor below collateral ratio")]
+    HealthFactorTooLow,
+    #[msg("Invalid oracle feed")]
+    InvalidOracleFeed,
+    #[msg("Loan has no outstanding debt")]
+    LoanNotActive,
+    #[msg("Unauthorized")]
+    Unauthorized,
+    #[msg("Invalid flash fee")]
+    InvalidFlashFee,
+    #[msg("Insufficient pool liquidity")]
+    InsufficientLiquidity,
+    #[msg("A flash loan is already active")]
+    FlashLoanActive,
+    #[msg("No active flash loan")]
+    NoActiveFlashLoan,
+    #[msg("Missing matching flash repay instruction")]
+    FlashRepayMissing,
+    #[msg("Flash repay does not match flash borrow")]
+    FlashLoanMismatch,
+    #[msg("Flash loans cannot be taken via CPI")]
+    FlashLoanCpiNotAllowed,
+    #[msg("MXE program not configured")]
+    MxeNotConfigured,
+    #[msg("Credit score computation already pending")]
+    ComputationPending,
+    #[msg("Callback does not match a pending computation")]
+    ComputationMismatch,
+    #[msg("Invalid MPC node set")]
+    InvalidMpcNodeSet,
+    #[msg("Invalid MPC threshold")]
+    InvalidMpcThreshold,
+    #[msg("Not enough MPC node attestations")]
+    InsufficientAttestations,
+    #[msg("Attestation expired")]
+    AttestationExpired,
+    #[msg("Credit score request expired")]
+    ComputationExpired,
+    #[msg("Invalid credit score configuration")]
+    InvalidCreditScoreConfig,
+    #[msg("Credit score requested too recently")]
+    CreditScoreCooldown,
+    #[msg("No credit score on record")]
+    NoCreditScore,
+    #[msg("Credit score expired")]
+    CreditScoreExpired,
+    #[msg("Invalid risk tiers")]
+    InvalidRiskTiers,
+    #[msg("Exceeds loan size limit for credit tier")]
+    ExceedsTierLoanLimit,
+    #[msg("Invalid MPC key rotation")]
+    InvalidMpcKeyRotation,
+    #[msg("MPC key rotation pending")]
+    MpcKeyRotationPending,
+    #[msg("Invalid credit line configuration")]
+    InvalidCreditLineConfig,
+    #[msg("Credit lines are disabled")]
+    CreditLinesDisabled,
+    #[msg("Credit score too low for a credit line")]
+    CreditScoreTooLow,
+    #[msg("Exceeds credit line limit")]
+    ExceedsCreditLine,
+    #[msg("Pool credit line exposure limit reached")]
+    CreditLineExposureLimit,
+    #[msg("Credit line has defaulted")]
+    CreditLineDefaulted,
+    #[msg("Credit line is not past due")]
+    CreditLineNotPastDue,
+    #[msg("Invalid credit delegation")]
+    InvalidDelegation,
+    #[msg("Credit delegation has expired")]
+    DelegationExpired,
+    #[msg("Borrow exceeds delegated credit")]
+    ExceedsDelegatedCredit,
+    #[msg("Delegated debt is still outstanding")]
+    DelegatedDebtOutstanding,
+    #[msg("Operation not allowed on a delegated loan")]
+    DelegatedLoan,
+    #[msg("Invalid loan guarantee")]
+    InvalidGuarantee,
+    #[msg("Invalid collateral asset configuration")]
+    InvalidCollateralAsset,
+    #[msg("Collateral asset is not supported")]
+    UnsupportedCollateral,
+    #[msg("Too many collateral positions")]
+    TooManyCollateralPositions,
+    #[msg("Invalid oracle price")]
+    InvalidOraclePrice,
+    #[msg("Oracle price is stale")]
+    StaleOraclePrice,
+    #[msg("Invalid pool caps")]
+    InvalidPoolCaps,
+    #[msg("Deposit would exceed the pool supply cap")]
+    SupplyCapExceeded,
+    #[msg("Borrow would exceed the pool borrow cap")]
+    BorrowCapExceeded,
+    #[msg("Borrow would exceed the per-user borrow limit")]
+    UserBorrowLimitExceeded,
+    #[msg("Isolated collateral can't be combined with other assets")]
+    IsolatedCollateralMix,
+    #[msg("Borrowing against isolated collateral is not enabled")]
+    IsolatedBorrowNotAllowed,
+    #[msg("Borrow would exceed the isolated asset's debt ceiling")]
+    DebtCeilingExceeded,
+    #[msg("Invalid e-mode category")]
+    InvalidEModeCategory,
+    #[msg("Collateral does not belong to the user's e-mode category")]
+    EModeCategoryMismatch,
+    #[msg("Invalid stake pool account")]
+    InvalidStakePool,
+    #[msg("Stake pool has not been updated this epoch")]
+    StakePoolNotUpdated,
+    #[msg("Mint has an unsupported Token-2022 extension")]
+    UnsupportedMintExtension,
+    #[msg("Mint's transfer hook program is not allowlisted")]
+    TransferHookNotAllowed,
+    #[msg("Too many transfer hook programs")]
+    TooManyTransferHookPrograms,
+    #[msg("Invalid stake account")]
+    InvalidStakeAccount,
+    #[msg("Stake account lockup is in force")]
+    StakeAccountLocked,
+    #[msg("Invalid loan term")]
+    InvalidLoanTerm,
+    #[msg("Credit line is past due")]
+    CreditLinePastDue,
+    #[msg("Too many reputation sources")]
+    TooManyReputationSources,
+    #[msg("Reputation record is not from a trusted pool")]
+    InvalidReputationSource,
+    #[msg("E-mode requires collateral in the category")]
+    NoEModeCollateral,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLLATERAL_RATIO: u16 = 15000;
+    const LIQUIDATION_THRESHOLD: u16 = 12000;
+
+    fn with_account<T>(owner: &Pubkey, data: &mut [u8], f: impl FnOnce(&AccountInfo) -> T) -> T {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        f(&AccountInfo::new(&key, false, false, &mut lamports, data, owner, false, 0))
+    }
+
+    /// `PriceUpdateV2` fixture: verification level, then price, confidence,
+    /// exponent and publish time
+    fn price_update(verification_level: u8, price: i64, exponent: i32, publish_time: i64) -> Vec<u8> {
+        let mut data = vec![0u8; PYTH_PRICE_OFFSET + 28 + 16];
+        data[PYTH_VERIFICATION_LEVEL_OFFSET] = verification_level;
+        data[PYTH_PRICE_OFFSET..PYTH_PRICE_OFFSET + 8].copy_from_slice(&price.to_le_bytes());
+        data[PYTH_PRICE_OFFSET + 16..PYTH_PRICE_OFFSET + 20].copy_from_slice(&exponent.to_le_bytes());
+        data[PYTH_PRICE_OFFSET + 20..PYTH_PRICE_OFFSET + 28].copy_from_slice(&publish_time.to_le_bytes());
+        data
+    }
+
+    /// SPL `StakePool` fixture with the fields the exchange rate is read from
+    fn stake_pool(pool_mint: &Pubkey, total_lamports: u64, pool_token_supply: u64, last_update_epoch: u64) -> Vec<u8> {
+        let mut data = vec![0u8; STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET + 8 + 64];
+        data[0] = STAKE_POOL_ACCOUNT_TYPE;
+        data[STAKE_POOL_MINT_OFFSET..STAKE_POOL_MINT_OFFSET + 32].copy_from_slice(pool_mint.as_ref());
+        data[STAKE_POOL_TOTAL_LAMPORTS_OFFSET..STAKE_POOL_TOTAL_LAMPORTS_OFFSET + 8]
+            .copy_from_slice(&total_lamports.to_le_bytes());
+        data[STAKE_POOL_TOKEN_SUPPLY_OFFSET..STAKE_POOL_TOKEN_SUPPLY_OFFSET + 8]
+            .copy_from_slice(&pool_token_supply.to_le_bytes());
+        data[STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET..STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET + 8]
+            .copy_from_slice(&last_update_epoch.to_le_bytes());
+        data
+    }
+
+    fn lending_pool() -> LendingPool {
+        LendingPool::try_deserialize_unchecked(&mut &vec![0u8; 8 + LendingPool::INIT_SPACE][..]).unwrap()
+    }
+
+    fn user_account() -> UserAccount {
+        UserAccount::try_deserialize_unchecked(&mut &[0u8; 8 + UserAccount::INIT_SPACE][..]).unwrap()
+    }
+
+    fn loan(borrowed_amount: u64, interest_rate: u16) -> Loan {
+        Loan {
+            borrower: Pubkey::default(),
+            user_account: Pubkey::default(),
+            collateral_amount: 0,
+            borrowed_amount,
+            interest_rate,
+            start_time: 0,
+            last_accrual: 0,
+            accrued_interest: 0,
+            is_liquidated: false,
+            bump: 0,
+            delegation: Pubkey::default(),
+            guarantor: Pubkey::default(),
+            guaranteed_amount: 0,
+            isolated_mint: Pubkey::default(),
+            due_at: LOAN_TERM,
+            late_payment_recorded: false,
+        }
+    }
+
+    #[test]
+    fn withdrawal_breaching_liquidation_threshold_is_rejected() {
+        let debt = 1_000_000;
+        let below_threshold = collateral_value(1_199_999, PRICE_PRECISION);
+
+        assert!(health_factor(below_threshold, debt) < LIQUIDATION_THRESHOLD as u64);
+        assert!(!is_withdrawal_healthy(below_threshold, debt, COLLATERAL_RATIO));
+    }
+
+    #[test]
+    fn withdrawal_at_collateral_ratio_boundary_succeeds() {
+        let debt = 1_000_000;
+
+        assert!(is_withdrawal_healthy(1_500_000, debt, COLLATERAL_RATIO));
+        assert!(!is_withdrawal_healthy(1_499_999, debt, COLLATERAL_RATIO));
+    }
+
+    #[test]
+    fn withdrawal_boundary_includes_accrued_interest() {
+        let mut loan = loan(1_000_000, 1000);
+        loan.accrue_interest(365 * 24 * 60 * 60);
+        assert_eq!(loan.total_owed(), 1_100_000);
+
+        assert!(is_withdrawal_healthy(1_650_000, loan.total_owed(), COLLATERAL_RATIO));
+        assert!(!is_withdrawal_healthy(1_500_000, loan.total_owed(), COLLATERAL_RATIO));
+    }
+
+    #[test]
+    fn withdrawal_without_debt_is_always_healthy() {
+        assert!(is_withdrawal_healthy(0, 0, COLLATERAL_RATIO));
+    }
+
+    #[test]
+    fn liquidation_after_partial_withdrawal_seizes_what_is_left() {
+        let mut user_account = user_account();
+        let mut loan = loan(1_000_000, 0);
+        user_account.collateral_deposited = 2_000_000;
+        user_account.amount_borrowed = 1_000_000;
+        loan.collateral_amount = 2_000_000;
+
+        user_account.withdraw_collateral(500_000, Some(&mut loan));
+        assert_eq!(loan.collateral_amount, 1_500_000);
+
+        let seized = user_account.seizable_collateral(&loan);
+        assert_eq!(seized, 1_500_000);
+        user_account.collateral_deposited -= seized;
+        assert_eq!(user_account.collateral_deposited, 0);
+    }
+
+    #[test]
+    fn collateral_seized_for_other_debt_caps_the_loan_seizure() {
+        let mut user_account = user_account();
+        let mut loan = loan(1_000_000, 0);
+        user_account.collateral_deposited = 2_000_000;
+        loan.collateral_amount = 2_000_000;
+
+        // Seized as a guarantor or delegator, without touching the own loan
+        user_account.collateral_deposited -= 600_000;
+
+        assert_eq!(user_account.seizable_collateral(&loan), 1_400_000);
+    }
+
+    #[test]
+    fn repaying_interest_leaves_principal_owed() {
+        let mut loan = loan(1_000_000, 1000);
+        loan.accrue_interest(365 * 24 * 60 * 60);
+
+        let principal_payment = loan.apply_repayment(1_000_000);
+
+        assert_eq!(principal_payment, 900_000);
+        assert_eq!(loan.borrowed_amount, 100_000);
+        assert_eq!(loan.total_owed(), 100_000);
+    }
+
+    #[test]
+    fn reputation_imports_only_from_trusted_pool_records() {
+        let mut lending_pool = lending_pool();
+        let source = Pubkey::new_unique();
+        lending_pool.reputation_sources[0] = source;
+        lending_pool.reputation_source_count = 1;
+
+        let user = Pubkey::new_unique();
+        let mut data = Vec::new();
+        Reputation {
+            owner: user,
+            successful_repayments: 3,
+            defaults: 1,
+            liquidations: 1,
+            total_repaid_volume: 5_000_000,
+            last_update: 0,
+            bump: 0,
+        }
+        .try_serialize(&mut data)
+        .unwrap();
+
+        let import = |key: &Pubkey, owner: &Pubkey, data: &mut [u8]| {
+            let mut lamports = 0;
+            let record = AccountInfo::new(key, false, false, &mut lamports, data, owner, false, 0);
+            lending_pool.imported_reputation(&record, &user)
+        };
+
+        let (record, _) = Pubkey::find_program_address(&[REPUTATION_SEED, user.as_ref()], &source);
+        let history = import(&record, &source, &mut data).unwrap();
+        assert_eq!(history.successful_repayments, 3);
+        assert_eq!(history.total_repaid_volume, 5_000_000);
+
+        let untrusted = Pubkey::new_unique();
+        let (untrusted_record, _) = Pubkey::find_program_address(&[REPUTATION_SEED, user.as_ref()], &untrusted);
+        assert_eq!(
+            import(&untrusted_record, &untrusted, &mut data).err(),
+            Some(ArciLendError::InvalidReputationSource.into())
+        );
+        assert_eq!(
+            import(&Pubkey::new_unique(), &source, &mut data).err(),
+            Some(ArciLendError::InvalidReputationSource.into())
+        );
+    }
+
+    fn credit_line(drawn: u64, interest_rate: u16) -> CreditLine {
+        CreditLine {
+            owner: Pubkey::default(),
+            limit: 1_000_000,
+            drawn,
+            accrued_interest: 0,
+            interest_rate,
+            last_accrual: 0,
+            due_at: LOAN_TERM,
+            defaulted: false,
+            bump: 0,
+        }
+    }
+
+    #[test]
+    fn credit_line_repayment_pays_interest_before_principal() {
+        let mut credit_line = credit_line(1_000_000, 1000);
+        credit_line.accrue_interest(365 * 24 * 60 * 60);
+
+        assert_eq!(credit_line.apply_repayment(50_000), 0);
+        assert_eq!(credit_line.apply_repayment(250_000), 200_000);
+        assert_eq!(credit_line.drawn, 800_000);
+        assert_eq!(credit_line.total_owed(), 800_000);
+    }
+
+    #[test]
+    fn credit_line_default_draws_on_the_loss_reserve_first() {
+        let mut lending_pool = lending_pool();
+        lending_pool.credit_line_loss_reserve = 30_000;
+        lending_pool.credit_line_exposure = 1_500_000;
+        lending_pool.total_borrowed = 2_000_000;
+
+        let mut credit_line = credit_line(1_000_000, 1000);
+        credit_line.accrue_interest(365 * 24 * 60 * 60);
+
+        let (loss, covered) = lending_pool.write_off_credit_line(&credit_line);
+
+        assert_eq!(loss, 1_100_000);
+        assert_eq!(covered, 30_000);
+        assert_eq!(lending_pool.credit_line_loss_reserve, 0);
+        assert_eq!(lending_pool.bad_debt, 1_070_000);
+        assert_eq!(lending_pool.credit_line_exposure, 500_000);
+        assert_eq!(lending_pool.total_borrowed, 1_000_000);
+    }
+
+    #[test]
+    fn credit_line_default_within_the_reserve_leaves_no_bad_debt() {
+        let mut lending_pool = lending_pool();
+        lending_pool.credit_line_loss_reserve = 2_000_000;
+        lending_pool.credit_line_exposure = 1_000_000;
+        lending_pool.total_borrowed = 1_000_000;
+
+        let (loss, covered) = lending_pool.write_off_credit_line(&credit_line(1_000_000, 0));
+
+        assert_eq!((loss, covered), (1_000_000, 1_000_000));
+        assert_eq!(lending_pool.credit_line_loss_reserve, 1_000_000);
+        assert_eq!(lending_pool.bad_debt, 0);
+    }
+
+    #[test]
+    fn liquidation_seizure_takes_the_bonus_from_the_collateral() {
+        assert_eq!(liquidation_seizure(1_000_000, LIQUIDATION_BONUS, PRICE_PRECISION, u64::MAX), 1_050_000);
+        assert_eq!(liquidation_seizure(1_000_000, LIQUIDATION_BONUS, 2 * PRICE_PRECISION, u64::MAX), 525_000);
+        assert_eq!(liquidation_seizure(1_000_000, LIQUIDATION_BONUS, PRICE_PRECISION, 800_000), 800_000);
+    }
+
+    #[test]
+    fn delegated_liquidation_leaves_the_delegators_own_loan_liquidatable() {
+        let mut delegator = user_account();
+        let mut own_loan = loan(500_000, 0);
+        delegator.collateral_deposited = 2_000_000;
+        own_loan.collateral_amount = 2_000_000;
+
+        // A delegatee's 1 SOL loan is liquidated against the delegator
+        let seized = liquidation_seizure(1_000_000, LIQUIDATION_BONUS, PRICE_PRECISION, delegator.collateral_deposited);
+        delegator.collateral_deposited -= seized;
+
+        let own_seized = delegator.seizable_collateral(&own_loan);
+        assert_eq!(own_seized, 950_000);
+        delegator.collateral_deposited -= own_seized;
+        assert_eq!(delegator.collateral_deposited, 0);
+    }
+
+    #[test]
+    fn guarantor_covers_the_shortfall_and_its_share_of_the_bonus() {
+        let debt = 650_000;
+        let guaranteed_amount = 600_000;
+
+        let borrower_seized = liquidation_seizure(debt, LIQUIDATION_BONUS, PRICE_PRECISION, 100_000);
+        let uncovered = debt - debt_covered(collateral_value(borrower_seized, PRICE_PRECISION), LIQUIDATION_BONUS);
+        let guarantor_seized =
+            liquidation_seizure(uncovered.min(guaranteed_amount), LIQUIDATION_BONUS, PRICE_PRECISION, 2_000_000);
+
+        assert_eq!(borrower_seized, 100_000);
+        assert_eq!(uncovered, 554_762);
+        assert_eq!(guarantor_seized, 582_500);
+        // Debt plus the bonus, all of it out of liquidated collateral
+        assert!(borrower_seized + guarantor_seized >= debt * (BASIS_POINTS + LIQUIDATION_BONUS) as u64 / BASIS_POINTS as u64);
+    }
+
+    #[test]
+    fn isolated_collateral_caps_debt_at_the_ceiling() {
+        let mint = Pubkey::new_unique();
+        let mut lending_pool = lending_pool();
+        lending_pool.isolated_borrowing_enabled = true;
+        lending_pool.collateral_assets[0] = CollateralAsset {
+            mint,
+            isolated: true,
+            debt_ceiling: 1_000_000,
+            ..Default::default()
+        };
+        lending_pool.collateral_asset_count = 1;
+
+        // Whoever's collateral backs the debt, a borrower or a delegator
+        let mut backer = user_account();
+        backer.add_collateral_position(mint, 10_000).unwrap();
+
+        assert_eq!(lending_pool.reserve_isolated_debt(&backer, 600_000).unwrap(), mint);
+        assert_eq!(
+            lending_pool.reserve_isolated_debt(&backer, 600_000).err(),
+            Some(ArciLendError::DebtCeilingExceeded.into())
+        );
+        assert_eq!(lending_pool.collateral_asset(&mint).unwrap().isolated_debt, 600_000);
+
+        assert_eq!(lending_pool.reserve_isolated_debt(&user_account(), 600_000).unwrap(), Pubkey::default());
+    }
+
+    #[test]
+    fn emode_ltv_only_applies_to_the_basket() {
+        let mut lending_pool = lending_pool();
+        lending_pool.emode_categories[0] = EModeCategory { id: 1, ltv_bps: 9000, liquidation_threshold: 10500, liquidation_bonus: 200 };
+        lending_pool.emode_category_count = 1;
+
+        let mut user_account = user_account();
+        user_account.collateral_deposited = 1_000_000;
+        // No score on record, so SOL counts at the default LTV
+        let sol_only = lending_pool.borrowing_power(&user_account, &BasketValue::default(), 1).unwrap();
+        assert_eq!(sol_only, 500_000);
+
+        // Plain SOL earns nothing extra from e-mode
+        user_account.emode_category = 1;
+        assert_eq!(lending_pool.borrowing_power(&user_account, &BasketValue::default(), 1).unwrap(), sol_only);
+
+        let basket = BasketValue { collateral_value: 1_000_000, ..Default::default() };
+        assert_eq!(lending_pool.borrowing_power(&user_account, &basket, 1).unwrap(), 1_400_000);
+    }
+
+    /// Initialized Token-2022 mint carrying `extensions`, each with default settings
+    fn mint_with_extensions(extensions: &[ExtensionType]) -> Vec<u8> {
+        use spl_token_2022::extension::{
+            default_account_state::DefaultAccountState, metadata_pointer::MetadataPointer,
+            mint_close_authority::MintCloseAuthority, transfer_fee::TransferFeeConfig, BaseStateWithExtensionsMut,
+            PodStateWithExtensionsMut,
+        };
+        use spl_token_2022::pod::PodMint;
+
+        let len = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(extensions).unwrap();
+        let mut data = vec![0u8; len];
+        let mut state = PodStateWithExtensionsMut::<PodMint>::unpack_uninitialized(&mut data).unwrap();
+        for extension in extensions {
+            let initialized = match extension {
+                ExtensionType::TransferFeeConfig => state.init_extension::<TransferFeeConfig>(true).map(|_| ()),
+                ExtensionType::MetadataPointer => state.init_extension::<MetadataPointer>(true).map(|_| ()),
+                ExtensionType::MintCloseAuthority => state.init_extension::<MintCloseAuthority>(true).map(|_| ()),
+                ExtensionType::DefaultAccountState => state.init_extension::<DefaultAccountState>(true).map(|_| ()),
+                ExtensionType::TransferHook => state.init_extension::<TransferHook>(true).map(|_| ()),
+                _ => unimplemented!(),
+            };
+            initialized.unwrap();
+        }
+        state.base.is_initialized = true.into();
+        state.init_account_type().unwrap();
+        data
+    }
+
+    #[test]
+    fn mint_extensions_outside_the_allowlist_are_rejected() {
+        let lending_pool = lending_pool();
+        let validate = |extensions: &[ExtensionType]| {
+            let mut data = mint_with_extensions(extensions);
+            with_account(&spl_token_2022::ID, &mut data, |mint| lending_pool.validate_mint_extensions(mint))
+        };
+
+        assert!(validate(&[ExtensionType::TransferFeeConfig, ExtensionType::MetadataPointer]).is_ok());
+        // A hook without a program never runs
+        assert!(validate(&[ExtensionType::TransferHook]).is_ok());
+
+        for extension in [ExtensionType::MintCloseAuthority, ExtensionType::DefaultAccountState] {
+            assert_eq!(
+                validate(&[ExtensionType::TransferFeeConfig, extension]).err(),
+                Some(ArciLendError::UnsupportedMintExtension.into())
+            );
+        }
+    }
+
+    #[test]
+    fn seized_stake_beyond_the_debt_and_bonus_goes_back_to_the_borrower() {
+        assert_eq!(stake_surplus_value(2_000_000, 1_000_000, LIQUIDATION_BONUS, PRICE_PRECISION), 950_000);
+        assert_eq!(stake_surplus_value(1_000_000, 1_000_000, LIQUIDATION_BONUS, PRICE_PRECISION), 0);
+    }
+
+    #[test]
+    fn late_installments_count_once_per_loan() {
+        let mut user_account = user_account();
+        let mut loan = loan(1_000_000, 0);
+
+        loan.apply_repayment(100_000);
+        user_account.record_repayment(&mut loan, 100_000, LOAN_TERM);
+        assert_eq!(user_account.late_payments, 0);
+
+        for _ in 0..9 {
+            loan.apply_repayment(100_000);
+            user_account.record_repayment(&mut loan, 100_000, LOAN_TERM + 1);
+        }
+
+        assert_eq!(loan.borrowed_amount, 0);
+        assert_eq!(user_account.late_payments, 1);
+        assert_eq!(user_account.closed_loans, 1);
+    }
+
+    #[test]
+    fn oracle_price_scales_to_lamports_per_base_unit() {
+        // 0.05 SOL per token with 6 decimals is 50 lamports per base unit
+        let mut data = price_update(PYTH_VERIFICATION_FULL, 5_000_000, -8, 1_000);
+        let price = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut data, |feed| oracle_price(feed, 6, 1_000));
+
+        assert_eq!(price.unwrap(), 50 * PRICE_PRECISION);
+    }
+
+    #[test]
+    fn oracle_price_divides_down_large_negative_exponents() {
+        // 1.234567e-6 SOL per token with 9 decimals scales to 1234.567, floored
+        let mut data = price_update(PYTH_VERIFICATION_FULL, 1_234_567, -12, 1_000);
+        let price = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut data, |feed| oracle_price(feed, 9, 1_000));
+
+        assert_eq!(price.unwrap(), 1_234);
+    }
+
+    #[test]
+    fn oracle_price_rejects_prices_that_round_to_zero() {
+        let mut data = price_update(PYTH_VERIFICATION_FULL, 999, -12, 1_000);
+        let price = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut data, |feed| oracle_price(feed, 9, 1_000));
+
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidOraclePrice.into());
+    }
+
+    #[test]
+    fn oracle_price_rejects_stale_updates() {
+        let mut data = price_update(PYTH_VERIFICATION_FULL, 5_000_000, -8, 1_000);
+
+        let at_limit = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut data, |feed| {
+            oracle_price(feed, 6, 1_000 + MAX_ORACLE_PRICE_AGE)
+        });
+        assert!(at_limit.is_ok());
+
+        let stale = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut data, |feed| {
+            oracle_price(feed, 6, 1_000 + MAX_ORACLE_PRICE_AGE + 1)
+        });
+        assert_eq!(stale.unwrap_err(), ArciLendError::StaleOraclePrice.into());
+    }
+
+    #[test]
+    fn oracle_price_rejects_non_positive_prices() {
+        for raw_price in [0, -5_000_000] {
+            let mut data = price_update(PYTH_VERIFICATION_FULL, raw_price, -8, 1_000);
+            let price = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut data, |feed| oracle_price(feed, 6, 1_000));
+
+            assert_eq!(price.unwrap_err(), ArciLendError::InvalidOraclePrice.into());
+        }
+    }
+
+    #[test]
+    fn oracle_price_rejects_partially_verified_or_foreign_accounts() {
+        let mut partial = price_update(0, 5_000_000, -8, 1_000);
+        let price = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut partial, |feed| oracle_price(feed, 6, 1_000));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidOracleFeed.into());
+
+        let mut data = price_update(PYTH_VERIFICATION_FULL, 5_000_000, -8, 1_000);
+        let price = with_account(&Pubkey::new_unique(), &mut data, |feed| oracle_price(feed, 6, 1_000));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidOracleFeed.into());
+
+        let mut truncated = data[..PYTH_PRICE_OFFSET + 20].to_vec();
+        let price = with_account(&PYTH_RECEIVER_PROGRAM_ID, &mut truncated, |feed| oracle_price(feed, 6, 1_000));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidOracleFeed.into());
+    }
+
+    #[test]
+    fn stake_pool_price_is_the_exchange_rate() {
+        let mint = Pubkey::new_unique();
+        let mut data = stake_pool(&mint, 1_050_000_000, 1_000_000_000, 500);
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut data, |pool| stake_pool_price(pool, &mint, 500));
+
+        assert_eq!(price.unwrap(), 1_050_000_000);
+    }
+
+    #[test]
+    fn stake_pool_price_rejects_pools_not_updated_this_epoch() {
+        let mint = Pubkey::new_unique();
+        let mut data = stake_pool(&mint, 1_050_000_000, 1_000_000_000, 499);
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut data, |pool| stake_pool_price(pool, &mint, 500));
+
+        assert_eq!(price.unwrap_err(), ArciLendError::StakePoolNotUpdated.into());
+    }
+
+    #[test]
+    fn stake_pool_price_rejects_zero_supply() {
+        let mint = Pubkey::new_unique();
+        let mut data = stake_pool(&mint, 1_050_000_000, 0, 500);
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut data, |pool| stake_pool_price(pool, &mint, 500));
+
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidStakePool.into());
+    }
+
+    #[test]
+    fn stake_pool_price_rejects_an_empty_pool() {
+        let mint = Pubkey::new_unique();
+        let mut data = stake_pool(&mint, 0, 1_000_000_000, 500);
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut data, |pool| stake_pool_price(pool, &mint, 500));
+
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidOraclePrice.into());
+    }
+
+    #[test]
+    fn stake_pool_price_rejects_mismatched_accounts() {
+        let mint = Pubkey::new_unique();
+
+        let mut other_mint = stake_pool(&Pubkey::new_unique(), 1_050_000_000, 1_000_000_000, 500);
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut other_mint, |pool| stake_pool_price(pool, &mint, 500));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidStakePool.into());
+
+        let mut data = stake_pool(&mint, 1_050_000_000, 1_000_000_000, 500);
+        let price = with_account(&Pubkey::new_unique(), &mut data, |pool| stake_pool_price(pool, &mint, 500));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidStakePool.into());
+
+        data[0] = STAKE_POOL_ACCOUNT_TYPE + 1;
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut data, |pool| stake_pool_price(pool, &mint, 500));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidStakePool.into());
+
+        let mut truncated = stake_pool(&mint, 1_050_000_000, 1_000_000_000, 500)[..STAKE_POOL_LAST_UPDATE_EPOCH_OFFSET].to_vec();
+        let price = with_account(&SPL_STAKE_POOL_PROGRAM_ID, &mut truncated, |pool| stake_pool_price(pool, &mint, 500));
+        assert_eq!(price.unwrap_err(), ArciLendError::InvalidStakePool.into());
+    }
+}
@@ -0,0 +1,151 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use arcilend::program::Arcilend;
+use arcilend::{CreditScoreInputs, MXE_SIGNER_SEED};
+
+declare_id!("E4wjodmZ96XRa5q5i7Mnr9pREJXjme462Cq2Kur99syz");
+
+/// Offline stand-in for the Arcium MXE. It records queued credit score
+/// computations and relays the cluster's result to ArciLend through the same
+/// signed callback the real MXE uses. Node attestations travel as Ed25519
+/// precompile instructions in the completing transaction.
+#[program]
+pub mod mock_mxe {
+    use super::*;
+
+    pub fn queue_computation(
+        ctx: Context<QueueComputation>,
+        computation_offset: u64,
+        callback_program: Pubkey,
+        inputs: CreditScoreInputs
+    ) -> Result<()> {
+        let computation = &mut ctx.accounts.computation;
+
+        computation.computation_offset = computation_offset;
+        computation.callback_program = callback_program;
+        computation.inputs = inputs;
+        computation.completed = false;
+        computation.bump = ctx.bumps.computation;
+
+        msg!("Computation {} queued", computation_offset);
+
+        Ok(())
+    }
+
+    pub fn complete_computation(
+        ctx: Context<CompleteComputation>,
+        encrypted_score: [u8; 32],
+        risk_adjusted_ltv: u16,
+        expiry: i64
+    ) -> Result<()> {
+        let computation = &mut ctx.accounts.computation;
+
+        let signer_seeds: &[&[&[u8]]] = &[&[MXE_SIGNER_SEED, &[ctx.bumps.mxe_signer]]];
+        let cpi_context = CpiContext::new_with_signer(
+            ctx.accounts.arcilend_program.to_account_info(),
+            arcilend::cpi::accounts::UpdateCreditScore {
+                lending_pool: ctx.accounts.lending_pool.to_account_info(),
+                user_account: ctx.accounts.user_account.to_account_info(),
+                credit_computation: ctx.accounts.credit_computation.to_account_info(),
+                mpc_authority: ctx.accounts.mxe_signer.to_account_info(),
+                instructions: ctx.accounts.instructions.to_account_info(),
+            },
+            signer_seeds,
+        );
+        arcilend::cpi::update_credit_score(
+            cpi_context,
+            computation.inputs.request_nonce,
+            encrypted_score,
+            risk_adjusted_ltv,
+            expiry,
+        )?;
+
+        computation.completed = true;
+
+        msg!("Computation {} completed", computation.computation_offset);
+
+        Ok(())
+    }
+}
+
+/// ---- Accounts ----
+
+#[derive(Accounts)]
+#[instruction(computation_offset: u64)]
+pub struct QueueComputation<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Computation::INIT_SPACE,
+        seeds = [COMPUTATION_SEED, computation_offset.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub computation: Account<'info, Computation>,
+
+    pub system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct CompleteComputation<'info> {
+    pub operator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [COMPUTATION_SEED, computation.computation_offset.to_le_bytes().as_ref()],
+        bump = computation.bump,
+        constraint = !computation.completed @ MockMxeError::AlreadyCompleted,
+        constraint = computation.callback_program == arcilend_program.key() @ MockMxeError::WrongCallbackProgram
+    )]
+    pub computation: Account<'info, Computation>,
+
+    /// CHECK: PDA signer presented to the callback program
+    #[account(
+        seeds = [MXE_SIGNER_SEED],
+        bump
+    )]
+    pub mxe_signer: AccountInfo<'info>,
+
+    /// CHECK: Validated by the callback program
+    #[account(mut)]
+    pub lending_pool: AccountInfo<'info>,
+
+    /// CHECK: Validated by the callback program
+    #[account(mut)]
+    pub user_account: AccountInfo<'info>,
+
+    /// CHECK: Validated by the callback program
+    #[account(mut)]
+    pub credit_computation: AccountInfo<'info>,
+
+    /// CHECK: Instructions sysvar, forwarded to the callback
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: AccountInfo<'info>,
+
+    pub arcilend_program: Program<'info, Arcilend>,
+}
+
+/// ---- Computation Struct ----
+
+#[account]
+#[derive(InitSpace)]
+pub struct Computation {
+    pub computation_offset: u64,
+    pub callback_program: Pubkey,
+    pub inputs: CreditScoreInputs,
+    pub completed: bool,
+    pub bump: u8,
+}
+
+pub const COMPUTATION_SEED: &[u8] = b"computation";
+
+/// ---- ERRORs ----
+#[error_code]
+pub enum MockMxeError {
+    #[msg("Computation already completed")]
+    AlreadyCompleted,
+    #[msg("Wrong callback program")]
+    WrongCallbackProgram,
+}
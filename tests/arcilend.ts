@@ -1,7 +1,9 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Arcilend } from "../target/types/arcilend";
-import { PublicKey, Keypair } from "@solana/web3.js";
+import { MockMxe } from "../target/types/mock_mxe";
+import { PublicKey, Keypair, Ed25519Program, StakeProgram, Authorized, Lockup } from "@solana/web3.js";
+import { assert } from "chai";
 
 describe("arcilend", () => {
   // Configure the client to use the local cluster.
@@ -9,6 +11,7 @@ describe("arcilend", () => {
   anchor.setProvider(provider);
 
   const program = anchor.workspace.arcilend as Program<Arcilend>;
+  const mockMxe = anchor.workspace.mockMxe as Program<MockMxe>;
 
   const mpcNode = Keypair.generate();
   const oracleFeed = Keypair.generate();
@@ -22,6 +25,43 @@ describe("arcilend", () => {
     program.programId,
   );
 
+  // Sends raw instructions and asserts the transaction fails with `code`
+  const expectError = async (promise: Promise<unknown>, code: string) => {
+    try {
+      await promise;
+    } catch (err) {
+      const logs: string[] = err.logs ?? err.transactionLogs ?? [];
+      assert.include(`${err.message}\n${logs.join("\n")}`, code);
+      return;
+    }
+    assert.fail(`expected ${code}`);
+  };
+
+  const pda = (seed: string, key: PublicKey) =>
+    PublicKey.findProgramAddressSync([Buffer.from(seed), key.toBuffer()], program.programId)[0];
+
+  // Funds a fresh keypair from the wallet and deposits `collateral` for it
+  const newDepositor = async (funding: number, collateral: number) => {
+    const user = Keypair.generate();
+    await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+      anchor.web3.SystemProgram.transfer({
+        fromPubkey: provider.wallet.publicKey,
+        toPubkey: user.publicKey,
+        lamports: funding,
+      })
+    ));
+
+    await program.methods.depositCollateral(new anchor.BN(collateral)).accounts({
+      user: user.publicKey,
+      lendingPool: lendingPoolPDA,
+      userAccount: pda("user_account", user.publicKey),
+      importedReputation: null,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }).signers([user]).rpc();
+
+    return user;
+  };
+
   it("Is initialized!", async () => {
     // Add your test here.
     const tx = await program.methods.initializePool(
@@ -52,4 +92,577 @@ describe("arcilend", () => {
   console.log(`NEXT_PUBLIC_MPC_NODE=${mpcNode.publicKey.toString()}`);
 
   });
+
+  it("Updates credit score through the mock MXE", async () => {
+    const user = provider.wallet.publicKey;
+    const computationOffset = new anchor.BN(Date.now());
+
+    const [userAccountPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("user_account"), user.toBuffer()],
+      program.programId,
+    );
+    const [creditComputationPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("credit_computation"), user.toBuffer()],
+      program.programId,
+    );
+    const [mxeComputationPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("computation"), computationOffset.toArrayLike(Buffer, "le", 8)],
+      mockMxe.programId,
+    );
+    const [mxeSignerPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mxe_signer")],
+      mockMxe.programId,
+    );
+
+    await program.methods.configureMxe().accounts({
+      authority: user,
+      lendingPool: lendingPoolPDA,
+      mxeProgram: mockMxe.programId,
+    }).rpc();
+
+    await program.methods.depositCollateral(
+      new anchor.BN(anchor.web3.LAMPORTS_PER_SOL)
+    ).accounts({
+      user,
+      lendingPool: lendingPoolPDA,
+      userAccount: userAccountPDA,
+      importedReputation: null,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }).rpc();
+
+    await program.methods.requestCreditScore(
+      computationOffset,
+      Array.from(Keypair.generate().publicKey.toBytes()),
+      new anchor.BN(1)
+    ).accounts({
+      lendingPool: lendingPoolPDA,
+      userAccount: userAccountPDA,
+      creditComputation: creditComputationPDA,
+      mxeComputation: mxeComputationPDA,
+      mxeProgram: mockMxe.programId,
+      user,
+      systemProgram: anchor.web3.SystemProgram.programId,
+    }).rpc();
+
+    const { requestNonce } = await program.account.creditScoreComputation.fetch(creditComputationPDA);
+
+    // The pool was initialized with mpcNode as a 1-of-1 node set
+    const encryptedScore = Buffer.alloc(32, 7);
+    const riskAdjustedLtv = 6500;
+    const expiry = new anchor.BN(Math.floor(Date.now() / 1000) + 300);
+    const ltvBytes = Buffer.alloc(2);
+    ltvBytes.writeUInt16LE(riskAdjustedLtv);
+    const message = Buffer.concat([
+      Buffer.from("arcilend:score_update"),
+      user.toBuffer(),
+      encryptedScore,
+      ltvBytes,
+      requestNonce.toArrayLike(Buffer, "le", 8),
+      expiry.toArrayLike(Buffer, "le", 8),
+    ]);
+
+    await mockMxe.methods.completeComputation(
+      Array.from(encryptedScore),
+      riskAdjustedLtv,
+      expiry
+    ).accounts({
+      operator: user,
+      computation: mxeComputationPDA,
+      mxeSigner: mxeSignerPDA,
+      lendingPool: lendingPoolPDA,
+      userAccount: userAccountPDA,
+      creditComputation: creditComputationPDA,
+      instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      arcilendProgram: program.programId,
+    }).preInstructions([
+      Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: mpcNode.secretKey,
+        message,
+      }),
+    ]).rpc();
+
+    const userAccount = await program.account.userAccount.fetch(userAccountPDA);
+    const computation = await program.account.creditScoreComputation.fetch(creditComputationPDA);
+
+    assert.equal(userAccount.riskAdjustedLtv, riskAdjustedLtv);
+    assert.isFalse(computation.pending);
+  });
+
+  describe("flash loans", () => {
+    const borrower = provider.wallet.publicKey;
+    const amount = new anchor.BN(anchor.web3.LAMPORTS_PER_SOL / 10);
+
+    const flashBorrowIx = (value: anchor.BN) =>
+      program.methods.flashBorrow(value).accounts({
+        borrower,
+        lendingPool: lendingPoolPDA,
+        instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      }).instruction();
+
+    const flashRepayIx = (value: anchor.BN) =>
+      program.methods.flashRepay(value).accounts({
+        borrower,
+        lendingPool: lendingPoolPDA,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).instruction();
+
+    const send = async (ixs: Promise<anchor.web3.TransactionInstruction>[]) =>
+      provider.sendAndConfirm(new anchor.web3.Transaction().add(...(await Promise.all(ixs))));
+
+    it("Repays a paired flash borrow with the fee", async () => {
+      const before = await program.account.lendingPool.fetch(lendingPoolPDA);
+      const lamportsBefore = await provider.connection.getBalance(lendingPoolPDA);
+
+      await send([flashBorrowIx(amount), flashRepayIx(amount)]);
+
+      const after = await program.account.lendingPool.fetch(lendingPoolPDA);
+      const lamportsAfter = await provider.connection.getBalance(lendingPoolPDA);
+      const fee = amount.muln(before.flashFeeBps).divn(10000);
+
+      assert.isTrue(after.flashLoanAmount.isZero());
+      assert.isTrue(after.totalFees.sub(before.totalFees).eq(fee));
+      assert.equal(lamportsAfter - lamportsBefore, fee.toNumber());
+    });
+
+    it("Rejects a flash borrow without a repay", async () => {
+      await expectError(send([flashBorrowIx(amount)]), "FlashRepayMissing");
+    });
+
+    it("Rejects a short flash repay", async () => {
+      await expectError(
+        send([flashBorrowIx(amount), flashRepayIx(amount.subn(1))]),
+        "FlashLoanMismatch"
+      );
+    });
+
+    it("Rejects a nested flash borrow", async () => {
+      await expectError(
+        send([flashBorrowIx(amount), flashBorrowIx(amount), flashRepayIx(amount), flashRepayIx(amount)]),
+        "FlashLoanActive"
+      );
+    });
+
+    it("Rejects a flash repay without a borrow", async () => {
+      await expectError(send([flashRepayIx(amount)]), "NoActiveFlashLoan");
+    });
+  });
+
+  describe("guaranteed loans", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const borrowGuaranteed = (borrower: Keypair, guarantor: Keypair, amount: number, guaranteed: number) =>
+      program.methods.borrow(new anchor.BN(amount), new anchor.BN(guaranteed)).accounts({
+        borrower: borrower.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: pda("user_account", borrower.publicKey),
+        loan: pda("loan", borrower.publicKey),
+        creditDelegation: null,
+        delegatorAccount: null,
+        guarantor: guarantor.publicKey,
+        guarantorAccount: pda("user_account", guarantor.publicKey),
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([borrower, guarantor]).rpc();
+
+    const repay = (borrower: Keypair, guarantor: Keypair, amount: anchor.BN) =>
+      program.methods.repay(amount).accounts({
+        borrower: borrower.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: pda("user_account", borrower.publicKey),
+        loan: pda("loan", borrower.publicKey),
+        reputation: pda("reputation", borrower.publicKey),
+        creditDelegation: null,
+        delegatorAccount: null,
+        guarantorAccount: pda("user_account", guarantor.publicKey),
+        stakeCollateral: null,
+        stakeAccount: null,
+        clock: null,
+        stakeProgram: null,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([borrower]).rpc();
+
+    it("Borrows beyond own capacity with a guarantee and releases it on repayment", async () => {
+      // 0.1 SOL at the default 50% LTV only backs 0.05 SOL on its own
+      const borrower = await newDepositor(0.5 * SOL, 0.1 * SOL);
+      const guarantor = await newDepositor(1.1 * SOL, 1 * SOL);
+      const guarantorPDA = pda("user_account", guarantor.publicKey);
+      const loanPDA = pda("loan", borrower.publicKey);
+
+      await borrowGuaranteed(borrower, guarantor, 0.2 * SOL, 0.15 * SOL);
+
+      let loan = await program.account.loan.fetch(loanPDA);
+      let guarantorAccount = await program.account.userAccount.fetch(guarantorPDA);
+      assert.ok(loan.guarantor.equals(guarantor.publicKey));
+      assert.equal(loan.guaranteedAmount.toNumber(), 0.15 * SOL);
+      assert.equal(guarantorAccount.amountGuaranteed.toNumber(), 0.15 * SOL);
+
+      // Once principal drops below the guarantee, the excess is handed back
+      await repay(borrower, guarantor, new anchor.BN(0.1 * SOL));
+
+      loan = await program.account.loan.fetch(loanPDA);
+      guarantorAccount = await program.account.userAccount.fetch(guarantorPDA);
+      assert.isTrue(loan.guaranteedAmount.eq(loan.borrowedAmount));
+      assert.isTrue(guarantorAccount.amountGuaranteed.eq(loan.guaranteedAmount));
+      assert.isBelow(loan.guaranteedAmount.toNumber(), 0.15 * SOL);
+
+      await repay(borrower, guarantor, new anchor.BN(SOL));
+
+      loan = await program.account.loan.fetch(loanPDA);
+      guarantorAccount = await program.account.userAccount.fetch(guarantorPDA);
+      assert.isTrue(loan.borrowedAmount.isZero());
+      assert.isTrue(loan.guaranteedAmount.isZero());
+      assert.isTrue(guarantorAccount.amountGuaranteed.isZero());
+    });
+
+    it("Rejects a guarantee beyond the guarantor's capacity", async () => {
+      const borrower = await newDepositor(0.5 * SOL, 0.1 * SOL);
+      const guarantor = await newDepositor(0.3 * SOL, 0.2 * SOL);
+
+      await expectError(
+        borrowGuaranteed(borrower, guarantor, 0.2 * SOL, 0.15 * SOL),
+        "ExceedsRiskAdjustedLTV"
+      );
+    });
+
+    it("Rejects a guaranteed loan that would open liquidatable", async () => {
+      const borrower = await newDepositor(0.5 * SOL, 0.1 * SOL);
+      const guarantor = await newDepositor(2.1 * SOL, 2 * SOL);
+
+      // 0.65 SOL of debt at a 120% threshold needs 0.78 SOL of backing,
+      // more than the 0.1 SOL posted plus the 0.6 SOL guarantee
+      await expectError(
+        borrowGuaranteed(borrower, guarantor, 0.65 * SOL, 0.6 * SOL),
+        "Undercollateralized"
+      );
+
+      const guarantorAccount = await program.account.userAccount.fetch(pda("user_account", guarantor.publicKey));
+      assert.isTrue(guarantorAccount.amountGuaranteed.isZero());
+    });
+  });
+
+  describe("credit delegation", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    it("Delegates, borrows, repays and revokes, and only liquidates an unhealthy delegator", async () => {
+      const delegator = await newDepositor(1.1 * SOL, 1 * SOL);
+      const delegatee = await newDepositor(0.2 * SOL, 0.01 * SOL);
+      const delegatorPDA = pda("user_account", delegator.publicKey);
+      const loanPDA = pda("loan", delegatee.publicKey);
+      const [delegationPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from("delegation"), delegator.publicKey.toBuffer(), delegatee.publicKey.toBuffer()],
+        program.programId,
+      );
+
+      await program.methods.delegateCredit(
+        new anchor.BN(0.3 * SOL),
+        new anchor.BN(Math.floor(Date.now() / 1000) + 3600)
+      ).accounts({
+        delegator: delegator.publicKey,
+        userAccount: delegatorPDA,
+        delegatee: delegatee.publicKey,
+        creditDelegation: delegationPDA,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([delegator]).rpc();
+
+      await program.methods.borrow(new anchor.BN(0.2 * SOL), new anchor.BN(0)).accounts({
+        borrower: delegatee.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: pda("user_account", delegatee.publicKey),
+        loan: loanPDA,
+        creditDelegation: delegationPDA,
+        delegatorAccount: delegatorPDA,
+        guarantor: null,
+        guarantorAccount: null,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([delegatee]).rpc();
+
+      let delegation = await program.account.creditDelegation.fetch(delegationPDA);
+      let delegatorAccount = await program.account.userAccount.fetch(delegatorPDA);
+      assert.equal(delegation.used.toNumber(), 0.2 * SOL);
+      assert.equal(delegatorAccount.delegatedDebt.toNumber(), 0.2 * SOL);
+
+      // 1 SOL of collateral comfortably covers 0.2 SOL of delegated debt
+      await expectError(
+        program.methods.liquidate().accounts({
+          lendingPool: lendingPoolPDA,
+          userAccount: pda("user_account", delegatee.publicKey),
+          loan: loanPDA,
+          reputation: pda("reputation", delegatee.publicKey),
+          oracleFeed: oracleFeed.publicKey,
+          liquidator: provider.wallet.publicKey,
+          guarantorAccount: null,
+          creditDelegation: delegationPDA,
+          delegatorAccount: delegatorPDA,
+          stakeCollateral: null,
+          stakeAccount: null,
+          clock: null,
+          stakeProgram: null,
+          systemProgram: anchor.web3.SystemProgram.programId,
+        }).rpc(),
+        "LoanNotLiquidatable"
+      );
+
+      const revoke = () =>
+        program.methods.revokeCreditDelegation().accounts({
+          delegator: delegator.publicKey,
+          userAccount: delegatorPDA,
+          creditDelegation: delegationPDA,
+        }).signers([delegator]).rpc();
+
+      await expectError(revoke(), "DelegatedDebtOutstanding");
+
+      await program.methods.repay(new anchor.BN(SOL)).accounts({
+        borrower: delegatee.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: pda("user_account", delegatee.publicKey),
+        loan: loanPDA,
+        reputation: pda("reputation", delegatee.publicKey),
+        creditDelegation: delegationPDA,
+        delegatorAccount: delegatorPDA,
+        guarantorAccount: null,
+        stakeCollateral: null,
+        stakeAccount: null,
+        clock: null,
+        stakeProgram: null,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([delegatee]).rpc();
+
+      delegation = await program.account.creditDelegation.fetch(delegationPDA);
+      delegatorAccount = await program.account.userAccount.fetch(delegatorPDA);
+      assert.isTrue(delegation.used.isZero());
+      assert.isTrue(delegatorAccount.delegatedDebt.isZero());
+
+      await revoke();
+
+      delegatorAccount = await program.account.userAccount.fetch(delegatorPDA);
+      assert.isTrue(delegatorAccount.creditDelegated.isZero());
+      assert.isNull(await provider.connection.getAccountInfo(delegationPDA));
+    });
+  });
+
+  describe("stake collateral", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+
+    const stakeAuthorities = async (stakeAccount: PublicKey) => {
+      const { value } = await provider.connection.getParsedAccountInfo(stakeAccount);
+      const { staker, withdrawer } = (value.data as anchor.web3.ParsedAccountData).parsed.info.meta.authorized;
+      return [staker, withdrawer];
+    };
+
+    it("Holds a delegated stake account and returns it once the loan is repaid", async () => {
+      const user = await newDepositor(3 * SOL, 0.1 * SOL);
+      const userAccountPDA = pda("user_account", user.publicKey);
+      const stakeCollateralPDA = pda("stake_collateral", user.publicKey);
+      const loanPDA = pda("loan", user.publicKey);
+      const stakeAccount = Keypair.generate();
+      const [validator] = (await provider.connection.getVoteAccounts()).current;
+
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+        StakeProgram.createAccount({
+          fromPubkey: user.publicKey,
+          stakePubkey: stakeAccount.publicKey,
+          authorized: new Authorized(user.publicKey, user.publicKey),
+          lockup: new Lockup(0, 0, PublicKey.default),
+          lamports: 2 * SOL,
+        }),
+        StakeProgram.delegate({
+          stakePubkey: stakeAccount.publicKey,
+          authorizedPubkey: user.publicKey,
+          votePubkey: new PublicKey(validator.votePubkey),
+        }),
+      ), [user, stakeAccount]);
+
+      // Only stake activated in an earlier epoch is accepted
+      const { epoch } = await provider.connection.getEpochInfo();
+      while ((await provider.connection.getEpochInfo()).epoch <= epoch) {
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+      }
+
+      await program.methods.depositStakeCollateral().accounts({
+        user: user.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        stakeAccount: stakeAccount.publicKey,
+        stakeCollateral: stakeCollateralPDA,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        stakeProgram: StakeProgram.programId,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([user]).rpc();
+
+      let userAccount = await program.account.userAccount.fetch(userAccountPDA);
+      assert.isAbove(userAccount.stakeCollateral.toNumber(), 0);
+      assert.deepEqual(await stakeAuthorities(stakeAccount.publicKey), [lendingPoolPDA.toBase58(), lendingPoolPDA.toBase58()]);
+
+      await program.methods.borrow(new anchor.BN(0.5 * SOL), new anchor.BN(0)).accounts({
+        borrower: user.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        loan: loanPDA,
+        creditDelegation: null,
+        delegatorAccount: null,
+        guarantor: null,
+        guarantorAccount: null,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([user]).rpc();
+
+      await expectError(
+        program.methods.withdrawStakeCollateral().accounts({
+          user: user.publicKey,
+          lendingPool: lendingPoolPDA,
+          userAccount: userAccountPDA,
+          stakeAccount: stakeAccount.publicKey,
+          stakeCollateral: stakeCollateralPDA,
+          loan: loanPDA,
+          clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+          stakeProgram: StakeProgram.programId,
+        }).signers([user]).rpc(),
+        "ActiveLoansExist"
+      );
+
+      await program.methods.repay(new anchor.BN(SOL)).accounts({
+        borrower: user.publicKey,
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        loan: loanPDA,
+        reputation: pda("reputation", user.publicKey),
+        creditDelegation: null,
+        delegatorAccount: null,
+        guarantorAccount: null,
+        stakeCollateral: stakeCollateralPDA,
+        stakeAccount: stakeAccount.publicKey,
+        clock: anchor.web3.SYSVAR_CLOCK_PUBKEY,
+        stakeProgram: StakeProgram.programId,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).signers([user]).rpc();
+
+      userAccount = await program.account.userAccount.fetch(userAccountPDA);
+      assert.isTrue(userAccount.stakeCollateral.isZero());
+      assert.isNull(await provider.connection.getAccountInfo(stakeCollateralPDA));
+      assert.deepEqual(await stakeAuthorities(stakeAccount.publicKey), [user.publicKey.toBase58(), user.publicKey.toBase58()]);
+    });
+  });
+
+  describe("credit lines", () => {
+    const SOL = anchor.web3.LAMPORTS_PER_SOL;
+    // Scored at 6500 by the mock MXE test above
+    const borrower = provider.wallet.publicKey;
+    const userAccountPDA = pda("user_account", borrower);
+    const creditLinePDA = pda("credit_line", borrower);
+    const reputationPDA = pda("reputation", borrower);
+
+    const draw = (amount: number) =>
+      program.methods.drawCreditLine(new anchor.BN(amount)).accounts({
+        borrower,
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        creditLine: creditLinePDA,
+      }).rpc();
+
+    const setLoanTerm = (loanTerm: number) =>
+      program.methods.setLoanTerm(new anchor.BN(loanTerm)).accounts({
+        authority: borrower,
+        lendingPool: lendingPoolPDA,
+      }).rpc();
+
+    before(async () => {
+      await program.methods.setCreditLineConfig(
+        6500,
+        new anchor.BN(0.5 * SOL),
+        new anchor.BN(SOL),
+        100 // 1% of every draw goes to the loss reserve
+      ).accounts({
+        authority: borrower,
+        lendingPool: lendingPoolPDA,
+      }).rpc();
+
+      await program.methods.fundLossReserve(new anchor.BN(0.01 * SOL)).accounts({
+        funder: borrower,
+        lendingPool: lendingPoolPDA,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).rpc();
+
+      // Short enough for the line to fall due within the test run
+      await setLoanTerm(10);
+
+      await program.methods.openCreditLine().accounts({
+        borrower,
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        creditLine: creditLinePDA,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).rpc();
+    });
+
+    it("Draws on the line, funding the loss reserve and recording utilization", async () => {
+      const before = await program.account.lendingPool.fetch(lendingPoolPDA);
+
+      await draw(0.2 * SOL);
+
+      const after = await program.account.lendingPool.fetch(lendingPoolPDA);
+      const creditLine = await program.account.creditLine.fetch(creditLinePDA);
+      const userAccount = await program.account.userAccount.fetch(userAccountPDA);
+
+      assert.equal(creditLine.drawn.toNumber(), 0.2 * SOL);
+      assert.equal(after.creditLineLossReserve.sub(before.creditLineLossReserve).toNumber(), 0.002 * SOL);
+      assert.equal(after.creditLineExposure.sub(before.creditLineExposure).toNumber(), 0.2 * SOL);
+      assert.isAtLeast(userAccount.maxUtilizationBps, 4000);
+      // Counted against the per-user borrow limit alongside loans
+      assert.equal(userAccount.creditLineDrawn.toNumber(), 0.2 * SOL);
+    });
+
+    it("Rejects a draw beyond the line's limit", async () => {
+      await expectError(draw(0.4 * SOL), "ExceedsCreditLine");
+    });
+
+    it("Repays the line and credits the borrower's reputation", async () => {
+      const before = await program.account.reputation.fetch(reputationPDA);
+
+      await program.methods.repayCreditLine(new anchor.BN(0.1 * SOL)).accounts({
+        payer: borrower,
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        reputation: reputationPDA,
+        creditLine: creditLinePDA,
+        systemProgram: anchor.web3.SystemProgram.programId,
+      }).rpc();
+
+      const after = await program.account.reputation.fetch(reputationPDA);
+      const creditLine = await program.account.creditLine.fetch(creditLinePDA);
+
+      assert.equal(after.totalRepaidVolume.sub(before.totalRepaidVolume).toNumber(), 0.1 * SOL);
+      assert.isAtLeast(creditLine.drawn.toNumber(), 0.1 * SOL);
+      assert.isBelow(creditLine.drawn.toNumber(), 0.2 * SOL);
+    });
+
+    it("Writes off a past-due line against the loss reserve", async () => {
+      const { dueAt } = await program.account.creditLine.fetch(creditLinePDA);
+      while ((await provider.connection.getBlockTime(await provider.connection.getSlot())) <= dueAt.toNumber()) {
+        await new Promise((resolve) => setTimeout(resolve, 1000));
+      }
+
+      await expectError(draw(0.01 * SOL), "CreditLinePastDue");
+
+      const before = await program.account.lendingPool.fetch(lendingPoolPDA);
+      const { drawn } = await program.account.creditLine.fetch(creditLinePDA);
+
+      await program.methods.defaultCreditLine().accounts({
+        lendingPool: lendingPoolPDA,
+        userAccount: userAccountPDA,
+        reputation: reputationPDA,
+        creditLine: creditLinePDA,
+      }).rpc();
+
+      const after = await program.account.lendingPool.fetch(lendingPoolPDA);
+      const creditLine = await program.account.creditLine.fetch(creditLinePDA);
+      const covered = before.creditLineLossReserve.sub(after.creditLineLossReserve);
+      const badDebt = after.badDebt.sub(before.badDebt);
+
+      assert.isTrue(creditLine.defaulted);
+      // The reserve (0.012 SOL) is used up before anything becomes bad debt
+      assert.isTrue(after.creditLineLossReserve.isZero());
+      assert.isTrue(covered.add(badDebt).gte(drawn));
+      assert.isTrue(before.creditLineExposure.sub(after.creditLineExposure).eq(drawn));
+
+      await setLoanTerm(90 * 24 * 60 * 60);
+    });
+  });
 });